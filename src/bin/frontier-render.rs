@@ -0,0 +1,123 @@
+extern crate frontier;
+extern crate orbclient;
+extern crate starship;
+
+use frontier::render::{self, Overlay};
+use frontier::svg;
+
+use orbclient::Color;
+
+use starship::cli::{Args, EXIT_LOAD, EXIT_OUTPUT, EXIT_USAGE, EXIT_VALIDATION};
+use starship::kind::BlockRegistry;
+use starship::simulation::TIMESTEP;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const USAGE: &str = "Usage: frontier-render [OPTIONS] SHIP
+
+Draws decks of a ship to image files, without a window.
+
+Options:
+    --deck N         render only deck N (default every deck)
+    --size PIXELS    pixels per tile (default 32)
+    --overlay NAME   none, bars, flow or the pressure of air, electricity, fuel, water or free_air
+                     (default bars). Flow shows one update of the simulation.
+    --blocks DIR     directory of block kinds and images (default res/blocks/)
+    --format FORMAT  png or svg (default png, or the extension of --output)
+    --output FILE    image to write (default the name of SHIP in the current directory).
+                     When rendering every deck, the index of each deck is added, as in ship-0.png.
+    --help           print this message";
+
+/// The output path of one deck, with its index before the extension if every deck is rendered
+fn deck_path(output: &str, format: &str, deck: Option<usize>) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().map_or("ship".into(), |stem| stem.to_string_lossy());
+    let name = match deck {
+        Some(deck) => format!("{}-{}.{}", stem, deck, format),
+        None => format!("{}.{}", stem, format)
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+fn write_svg(path: &str, svg: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(svg.as_bytes())
+}
+
+fn main() {
+    let mut deck = None;
+    let mut size = render::TILE_SIZE;
+    let mut overlay = Overlay::Bars;
+    let mut blocks = "res/blocks/".to_string();
+    let mut format = None;
+    let mut output = None;
+    let mut ship_path = None;
+
+    let mut args = Args::new("frontier-render");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deck" => deck = Some(args.value("--deck").parse::<usize>().unwrap_or_else(|_| args.fail(EXIT_USAGE, "--deck must be a whole number"))),
+            "--size" => size = args.value("--size").parse::<i32>().ok().filter(|&size| size > 0).unwrap_or_else(|| args.fail(EXIT_USAGE, "--size must be a positive whole number")),
+            "--overlay" => {
+                let name = args.value("--overlay");
+                overlay = Overlay::from_name(&name).unwrap_or_else(|| args.fail(EXIT_USAGE, &format!("unknown overlay {}", name)));
+            },
+            "--blocks" => blocks = args.value("--blocks"),
+            "--format" => format = Some(args.value("--format")),
+            "--output" => output = Some(args.value("--output")),
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => if arg.starts_with("--") || ship_path.is_some() {
+                args.fail(EXIT_USAGE, &format!("unexpected argument {}\n{}", arg, USAGE));
+            } else {
+                ship_path = Some(arg);
+            }
+        }
+    }
+
+    let ship_path = ship_path.unwrap_or_else(|| args.fail(EXIT_USAGE, USAGE));
+    let output = output.unwrap_or_else(|| Path::new(&ship_path).file_name().map_or("ship".to_string(), |name| name.to_string_lossy().into_owned()));
+    let format = format.unwrap_or_else(|| match Path::new(&output).extension() {
+        Some(extension) if extension == "svg" => "svg".to_string(),
+        _ => "png".to_string()
+    });
+    if format != "png" && format != "svg" {
+        args.fail(EXIT_USAGE, &format!("unknown format {}", format));
+    }
+
+    let registry = BlockRegistry::load(&blocks).unwrap_or_else(|err| args.fail(EXIT_LOAD, &format!("{}: {}", blocks, err)));
+    let images = render::load_images(&blocks).unwrap_or_else(|err| args.fail(EXIT_LOAD, &format!("{}: {}", blocks, err)));
+    let mut ship = starship::load(&ship_path).unwrap_or_else(|err| args.fail(EXIT_LOAD, &format!("{}: {}", ship_path, err)));
+
+    if let Err(err) = ship.validate(&registry) {
+        args.fail(EXIT_VALIDATION, &format!("{}: {}", ship_path, err));
+    }
+
+    let decks: Vec<usize> = match deck {
+        Some(deck) if deck < ship.decks.len() => vec![deck],
+        Some(deck) => args.fail(EXIT_USAGE, &format!("{} has no deck {}", ship_path, deck)),
+        None => (0 .. ship.decks.len()).collect()
+    };
+
+    // The flow overlay shows what moves during the first tick
+    let reports = if overlay == Overlay::Flow { ship.update(TIMESTEP, &registry) } else { Vec::new() };
+
+    let colors: BTreeMap<String, Color> = images.iter().map(|(name, image)| (name.clone(), svg::average_color(image))).collect();
+    for &d in decks.iter() {
+        let path = deck_path(&output, &format, if deck.is_some() { None } else { Some(d) });
+        let result = if format == "svg" {
+            write_svg(&path, &svg::deck_svg(&ship.decks[d], &registry, &colors, size, &overlay, reports.get(d)))
+        } else {
+            render::save_png(&render::render_deck(&ship.decks[d], &registry, &images, size, &overlay, reports.get(d)), &path)
+        };
+        match result {
+            Ok(()) => println!("{}", path),
+            Err(err) => args.fail(EXIT_OUTPUT, &format!("{}: {}", path, err))
+        }
+    }
+}
@@ -0,0 +1,79 @@
+/// Tile sizes in pixels of the zoom levels, from zoomed out to zoomed in
+pub const ZOOMS: [i32; 4] = [8, 16, 32, 64];
+
+/// Maps tile positions on a deck to window positions
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    /// Window position of the top left corner of tile (0, 0)
+    pub x: i32,
+    pub y: i32,
+    /// Tile size in pixels, usually one of `ZOOMS`
+    size: i32
+}
+
+impl Camera {
+    pub fn new(x: i32, y: i32) -> Camera {
+        Camera::with_size(x, y, 32)
+    }
+
+    /// A camera with tiles of any size, such as for rendering a deck to a file
+    pub fn with_size(x: i32, y: i32, size: i32) -> Camera {
+        Camera {
+            x: x,
+            y: y,
+            size: size
+        }
+    }
+
+    /// Size of a tile in pixels
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// Window position of the top left corner of a tile
+    pub fn to_window(&self, tile_x: isize, tile_y: isize) -> (i32, i32) {
+        (self.x + tile_x as i32 * self.size(), self.y + tile_y as i32 * self.size())
+    }
+
+    /// The tile containing a window position
+    pub fn to_tile(&self, x: i32, y: i32) -> (isize, isize) {
+        (((x - self.x) as f64/self.size() as f64).floor() as isize, ((y - self.y) as f64/self.size() as f64).floor() as isize)
+    }
+
+    pub fn pan(&mut self, dx: i32, dy: i32) {
+        self.x += dx;
+        self.y += dy;
+    }
+
+    /// Change the zoom level, keeping the point under the window position (x, y) in place
+    fn set_size(&mut self, size: i32, x: i32, y: i32) {
+        let old_size = self.size;
+        self.size = size;
+        self.x = x - (x - self.x) * size/old_size;
+        self.y = y - (y - self.y) * size/old_size;
+    }
+
+    pub fn zoom_in(&mut self, x: i32, y: i32) {
+        if let Some(&size) = ZOOMS.iter().find(|&&size| size > self.size) {
+            self.set_size(size, x, y);
+        }
+    }
+
+    pub fn zoom_out(&mut self, x: i32, y: i32) {
+        if let Some(&size) = ZOOMS.iter().rev().find(|&&size| size < self.size) {
+            self.set_size(size, x, y);
+        }
+    }
+
+    /// Center the tiles from `min` to `max` in a view, at the largest zoom where they fit
+    pub fn fit(&mut self, min: (isize, isize), max: (isize, isize), view_x: i32, view_y: i32, view_w: i32, view_h: i32) {
+        let tiles_w = (max.0 - min.0 + 1) as i32;
+        let tiles_h = (max.1 - min.1 + 1) as i32;
+
+        self.size = ZOOMS.iter().rev().cloned().find(|&size| tiles_w * size <= view_w && tiles_h * size <= view_h).unwrap_or(ZOOMS[0]);
+
+        let size = self.size;
+        self.x = view_x + (view_w - tiles_w * size)/2 - min.0 as i32 * size;
+        self.y = view_y + (view_h - tiles_h * size)/2 - min.1 as i32 * size;
+    }
+}
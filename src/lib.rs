@@ -0,0 +1,8 @@
+extern crate orbclient;
+extern crate orbimage;
+extern crate png;
+extern crate starship;
+
+pub mod camera;
+pub mod render;
+pub mod svg;
@@ -1,152 +1,637 @@
+extern crate frontier;
 extern crate orbclient;
 extern crate orbfont;
 extern crate orbimage;
 extern crate starship;
 
-use orbclient::{Color, EventOption, Window, K_UP, K_DOWN, K_DEL, K_ESC, K_F1, K_F2, K_F5, K_F6};
+mod panels;
+
+use frontier::camera::Camera;
+use frontier::render::{self, Overlay};
+use panels::{Field, GRAPH_H, PALETTE_ROW, PALETTE_W, INSPECTOR_W, PANEL_Y};
+
+use orbclient::{Color, EventOption, Window, K_UP, K_DOWN, K_LEFT, K_RIGHT, K_PGUP, K_PGDN, K_HOME, K_DEL, K_ESC, K_CTRL, K_LEFT_SHIFT, K_RIGHT_SHIFT, K_BKSP, K_ENTER, K_F1, K_F2, K_F3, K_F4, K_F5, K_F6, K_F7, K_F8, K_F9, K_F10, K_F11};
 use orbfont::Font;
 use orbimage::Image;
 
-use starship::block::{Block, BlockResource};
+use starship::block::Block;
+use starship::blueprint::{Blueprint, BlueprintLibrary};
+use starship::cli::{self, Args, EXIT_LOAD, EXIT_USAGE};
+use starship::deck::Deck;
+use starship::edit::Edit;
+use starship::engine::{Command, Engine, Snapshot};
+use starship::kind::{BlockKind, BlockRegistry, BlockVariant};
+use starship::replay::{Recording, Replay};
+use starship::selection::{self, Transform};
+use starship::ship::Ship;
+use starship::simulation::TIMESTEP;
+use starship::telemetry::{Source, Telemetry, Watch};
 
-use std::borrow::Borrow;
-use std::collections::BTreeMap;
-use std::cmp::max;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-fn main(){
-    let mut window = Window::new_flags(100, 100, 1024, 768, "Frontier", true).unwrap();
-    let font = Font::from_path("res/FiraMono-Regular.ttf").unwrap();
-
-    let ship_lock = Arc::new(Mutex::new(starship::load("res/ship.json").unwrap()));
-
-    let mut block_kinds: BTreeMap<String, Image> = BTreeMap::new();
-    for entry_result in fs::read_dir("res/blocks/").unwrap() {
-        let entry = entry_result.unwrap();
-        let path = entry.path();
-        if path.is_dir() {
-            let mut image_path = path.clone();
-            image_path.push("image.png");
-            if image_path.is_file() {
-                block_kinds.insert(entry.file_name().into_string().unwrap(), Image::from_path(&image_path).unwrap());
-            }
+const USAGE: &'static str = "Usage: frontier [OPTIONS] [SHIP]
+
+Options:
+    --res DIR        directory of the font, block kinds and blueprints (default res)
+    --record FILE    record every tick and edit to FILE, which is written on quitting or opening another ship
+    --replay FILE    play back a recording instead of editing a ship
+    --help           print this message
+
+SHIP defaults to ship.json in the resource directory.";
+
+/// Seconds between autosaves of unsaved edits
+const AUTOSAVE_SECS: u64 = 60;
+
+/// Samples of resource totals kept for the graphs, taken every `TELEMETRY_INTERVAL` ticks
+const TELEMETRY_SAMPLES: usize = 6000;
+const TELEMETRY_INTERVAL: u64 = 10;
+
+/// Pixels panned by each press of an arrow key
+const PAN_STEP: i32 = 64;
+
+/// Ticks skipped by < and > while replaying
+const SKIP_TICKS: u64 = 100;
+
+/// What the left button does on the deck
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Tool {
+    /// Select blocks, and drag them to move them
+    Select,
+    /// Fill a rectangle with the brush
+    Fill,
+    /// Draw a line of the brush
+    Line,
+    /// Stamp the chosen blueprint
+    Stamp
+}
+
+/// What the text typed at the prompt is for
+#[derive(Clone, Debug, PartialEq)]
+enum Prompt {
+    /// Name of a new blueprint of the selection
+    BlueprintName,
+    /// New name of the current deck
+    DeckName,
+    /// Confirm deleting the current deck by pressing Y
+    DeleteDeck,
+    /// Path to save the ship to
+    SaveAs,
+    /// Path of a ship to open
+    Open,
+    /// Confirm an action that would lose unsaved edits by pressing Y
+    Discard(Action),
+    /// Offer to load the autosave left by a session that did not quit cleanly
+    Recover
+}
+
+/// Something that replaces or closes the ship, losing unsaved edits
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Quit,
+    /// Load the ship file again
+    Reload,
+    New,
+    Open(String),
+    /// Load the autosave of the ship, keeping its path
+    Recover
+}
+
+/// A drag with the left button, from press to release
+enum Drag {
+    /// Moving the selection, with the tile it was grabbed at and where each block started
+    Move { grab: (isize, isize), starts: Vec<(usize, isize, isize)>, moved: bool },
+    /// Selecting the blocks in a rectangle
+    Band { start: (isize, isize), end: (isize, isize) },
+    /// Placing the brush with the fill or line tool
+    Draw { start: (isize, isize), end: (isize, isize) }
+}
+
+/// Zoom and center the camera on the whole ship, below the header and right of `left`
+fn fit(camera: &mut Camera, ship: &Ship, left: i32, window_w: u32, window_h: u32) {
+    if let Some((min, max)) = ship.bounds() {
+        camera.fit(min, max, left, 32, window_w as i32 - left, window_h as i32 - 32);
+    }
+}
+
+/// Where edits to a ship are autosaved, next to its file, or in the resource directory for a new ship
+fn autosave_path(ship_path: &Option<String>, res: &Path) -> String {
+    match *ship_path {
+        Some(ref path) => format!("{}.autosave", path),
+        None => res.join("untitled.autosave").to_string_lossy().into_owned()
+    }
+}
+
+/// Where recorded totals are exported, next to the ship file, or in the resource directory for a new ship
+fn telemetry_path(ship_path: &Option<String>, res: &Path) -> String {
+    match *ship_path {
+        Some(ref path) => format!("{}.telemetry.csv", path),
+        None => res.join("untitled.telemetry.csv").to_string_lossy().into_owned()
+    }
+}
+
+/// Load and validate a ship file
+fn open(path: &str, registry: &BlockRegistry) -> starship::Result<Ship<'static>> {
+    let ship = starship::load(path)?;
+    ship.validate(registry)?;
+    Ok(ship)
+}
+
+/// Run an action, unless it would lose unsaved edits, in which case ask first
+fn request(action: Action, dirty: bool, prompt: &mut Option<(Prompt, String)>, pending: &mut Option<Action>) {
+    if dirty {
+        *prompt = Some((Prompt::Discard(action), String::new()));
+    } else {
+        *pending = Some(action);
+    }
+}
+
+/// Send a command to the simulation thread without waiting for it. Its effect shows in a later snapshot,
+/// and its error, if any, is printed when it arrives. Returns false, after printing the error, if the thread has stopped.
+fn send(engine: &Engine, command: Command) -> bool {
+    match engine.send(command) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("{}", err);
+            false
         }
     }
+}
 
-    let mut title;
-    {
-        let ship = ship_lock.lock().unwrap();
-        assert!(ship.decks.len() > 0);
-        title = font.render(&format!("{} - {} - {}", ship.name, ship.current_deck, ship.decks[ship.current_deck].name), 24.0);
+/// Send a command to the simulation thread and wait for it, keeping the snapshot published after it.
+/// Edits are sent this way, since the indexes of the next edit depend on the ship after this one.
+/// Returns false, after printing the error, if it failed.
+fn call(engine: &Engine, snapshot: &mut Arc<Snapshot>, command: Command) -> bool {
+    match engine.call(command) {
+        Ok(latest) => {
+            *snapshot = latest;
+            true
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
     }
+}
 
-    let running = Arc::new(AtomicBool::new(true));
-    let redraw = Arc::new(AtomicBool::new(true));
+/// Finish the recording, if there is one, and write it
+fn stop_recording(engine: &Engine, snapshot: &mut Arc<Snapshot>, record_path: &mut Option<String>) {
+    if let Some(path) = record_path.take() {
+        match engine.call(Command::StopRecording) {
+            Ok(latest) => {
+                *snapshot = latest;
+                println!("Saved recording {}", path);
+            },
+            Err(err) => eprintln!("{}: {}", path, err)
+        }
+    }
+}
 
-    let running_update = running.clone();
-    let redraw_update = redraw.clone();
-    let ship_update = ship_lock.clone();
-    let handle = thread::spawn(move || {
-        while running_update.load(Ordering::SeqCst) {
-            {
-                let mut ship = ship_update.lock().unwrap();
-                if ship.update() {
-                    redraw_update.store(true, Ordering::SeqCst);
-                }
+/// Switch the simulation thread to the deck shown by the editor, if it is on another one.
+/// Returns true if the deck changed, after which `deck` is the one actually shown.
+fn set_deck(engine: &Engine, snapshot: &mut Arc<Snapshot>, deck: &mut usize) -> bool {
+    if *deck == snapshot.ship.current_deck {
+        return false;
+    }
+    call(engine, snapshot, Command::SetDeck(*deck));
+    *deck = snapshot.ship.current_deck;
+    true
+}
+
+/// Place a new block of a kind, or one of its variants, at a tile
+fn place(engine: &Engine, snapshot: &mut Arc<Snapshot>, registry: &BlockRegistry, deck: usize, x: isize, y: isize, kind: &BlockKind, variant: Option<&BlockVariant>) {
+    let block = match variant {
+        Some(variant) => Some(Block::from_variant(x, y, kind, variant)),
+        None => Block::from_kind(x, y, &kind.name, registry)
+    };
+    if let Some(block) = block {
+        let index = snapshot.ship.decks[deck].blocks.len();
+        call(engine, snapshot, Command::Edit(Edit::AddBlock { deck: deck, index: index, block: block }));
+    }
+}
+
+/// Set the amount or capacity typed into an inspector field.
+/// Amounts are limited to the capacity, and capacities must be positive.
+fn commit(engine: &Engine, snapshot: &mut Arc<Snapshot>, deck: usize, field: &Field) -> Result<(), String> {
+    let mut resource = match snapshot.ship.decks[deck].blocks.get(field.block).and_then(|block| block.resources.get(field.resource.as_str())) {
+        Some(resource) => *resource,
+        None => return Err(format!("block {} no longer has {}", field.block, field.resource))
+    };
+
+    let value = match field.text.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => value,
+        _ => return Err(format!("{} is not a valid amount", field.text))
+    };
+    if field.capacity {
+        if value <= 0.0 {
+            return Err("capacity must be more than 0".to_string());
+        }
+        resource.capacity = value;
+        resource.amount = resource.amount.min(value);
+    } else {
+        resource.amount = value.min(resource.capacity);
+    }
+
+    let edit = Edit::EditResource {
+        deck: deck,
+        index: field.block,
+        resource: field.resource.clone(),
+        value: Some(resource)
+    };
+    match engine.call(Command::Edit(edit)) {
+        Ok(latest) => {
+            *snapshot = latest;
+            Ok(())
+        },
+        Err(err) => Err(format!("{}", err))
+    }
+}
+
+/// Rotate or mirror the selected blocks within their bounds
+fn transform(engine: &Engine, snapshot: &mut Arc<Snapshot>, deck: usize, selection: &BTreeSet<usize>, transform: Transform) {
+    let moves = {
+        let blocks = &snapshot.ship.decks[deck].blocks;
+        let (min, max) = match selection::bounds(selection.iter().filter_map(|&i| blocks.get(i))) {
+            Some(bounds) => bounds,
+            None => return
+        };
+        selection.iter().filter_map(|&i| blocks.get(i).map(|block| {
+            let (x, y) = transform.apply(block.x, block.y, min, max);
+            (i, x, y)
+        })).collect::<Vec<_>>()
+    };
+    call(engine, snapshot, Command::Edit(Edit::move_blocks(deck, &moves)));
+}
+
+/// Draw a square frame of `size` pixels, with lines `thickness` pixels wide
+fn outline(window: &mut Window, x: i32, y: i32, size: i32, thickness: i32, color: Color) {
+    window.rect(x, y, size as u32, thickness as u32, color);
+    window.rect(x, y, thickness as u32, size as u32, color);
+    window.rect(x, y + size - thickness, size as u32, thickness as u32, color);
+    window.rect(x + size - thickness, y, thickness as u32, size as u32, color);
+}
+
+fn main(){
+    let mut res = PathBuf::from("res");
+    let mut ship_path = None;
+    let mut record_path = None;
+    let mut replay_path = None;
+
+    let mut args = Args::new("frontier");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--res" => res = PathBuf::from(args.value("--res")),
+            "--record" => record_path = Some(args.value("--record")),
+            "--replay" => replay_path = Some(args.value("--replay")),
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => if arg.starts_with("--") || ship_path.is_some() {
+                args.fail(EXIT_USAGE, &format!("unexpected argument {}\n{}", arg, USAGE));
+            } else {
+                ship_path = Some(arg);
             }
-            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    if replay_path.is_some() && (record_path.is_some() || ship_path.is_some()) {
+        args.fail(EXIT_USAGE, &format!("--replay cannot be used with --record or a ship\n{}", USAGE));
+    }
+    let path = ship_path.unwrap_or_else(|| res.join("ship.json").to_string_lossy().into_owned());
+
+    let mut window = Window::new_flags(100, 100, 1024, 768, "Frontier", true).unwrap();
+    let font = Font::from_path(&res.join("FiraMono-Regular.ttf").to_string_lossy()).unwrap();
+
+    let blocks_dir = res.join("blocks");
+    let mut block_registry = match BlockRegistry::load(&blocks_dir) {
+        Ok(registry) => Arc::new(registry),
+        Err(err) => args.fail(EXIT_LOAD, &format!("{}: {}", blocks_dir.display(), err))
+    };
+
+    // Blueprints are optional, so a broken one only empties the browser
+    let blueprints_dir = res.join("blueprints");
+    let mut blueprints = match BlueprintLibrary::load(&blueprints_dir) {
+        Ok(blueprints) => blueprints,
+        Err(err) => {
+            eprintln!("{}: {}", blueprints_dir.display(), err);
+            BlueprintLibrary::new()
+        }
+    };
+
+    // A replay shows its own ship with its own block kinds, and cannot be edited
+    let replay = replay_path.as_ref().map(|replay_path| {
+        let loaded = Recording::load(replay_path).and_then(|recording| {
+            recording.ship.validate(&recording.registry())?;
+            Replay::new(recording)
+        });
+        match loaded {
+            Ok(replay) => replay,
+            Err(err) => args.fail(cli::exit_code(&err), &format!("{}: {}", replay_path, err))
         }
     });
+    if let Some(ref replay) = replay {
+        block_registry = Arc::new(replay.registry().clone());
+    }
+    let replaying = replay.is_some();
+
+    // The simulation thread owns the ship, which is edited by sending it commands and drawn from its snapshots
+    let engine = match replay {
+        Some(replay) => Engine::replay(replay, TELEMETRY_INTERVAL),
+        None => match open(&path, &block_registry) {
+            Ok(ship) => Engine::new(ship, block_registry.clone(), TIMESTEP, TELEMETRY_INTERVAL),
+            Err(err) => args.fail(cli::exit_code(&err), &format!("{}: {}", path, err))
+        }
+    };
+    let mut snapshot = engine.snapshot();
+    // None while editing a new ship that has not been saved
+    let mut ship_path = if replaying { None } else { Some(path) };
+
+    let block_kinds = match render::load_images(&blocks_dir) {
+        Ok(images) => images,
+        Err(err) => args.fail(EXIT_LOAD, &format!("{}: {}", blocks_dir.display(), err))
+    };
+
+    // Rendered again whenever the text changes, such as after renaming a deck
+    let mut title_text = String::new();
+    let mut title = font.render(&title_text, 24.0);
+
+    // Samples sent by the simulation thread, for the graphs
+    let mut telemetry = Telemetry::new(TELEMETRY_SAMPLES, TELEMETRY_INTERVAL);
 
-    let mut dragging = None;
+    let start = Instant::now();
+    let mut camera = Camera::new(0, render::TILE_SIZE);
+    fit(&mut camera, &snapshot.ship, PALETTE_W, window.width(), window.height());
+    let mut scaled_size = 0;
+    let mut scaled_kinds = BTreeMap::new();
+    let mut panning = None;
+    let mut mouse = (window.width() as i32/2, window.height() as i32/2);
+    // Cleared once the recording has been written, or if it could not be started
+    let mut record_path = match record_path {
+        Some(path) => if call(&engine, &mut snapshot, Command::Record(path.clone())) { Some(path) } else { None },
+        None => None
+    };
+    let mut ctrl = false;
+    let mut shift = false;
+    let mut drag: Option<Drag> = None;
+    let mut tool = Tool::Select;
+    let mut clipboard: Vec<Block<'static>> = vec![];
     let mut editing = None;
-    let mut show_info = true;
+    let mut hover = None;
+    let mut overlay = Overlay::Bars;
+    let mut show_palette = true;
+    let mut show_blueprints = false;
+    let mut show_graphs = false;
+    // Index of the blueprint stamped by the stamp tool
+    let mut blueprint = 0;
+    // Offer to recover an autosave that is newer than the ship file
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let autosave = autosave_path(&ship_path, &res);
+    let mut prompt: Option<(Prompt, String)> = if ship_path.as_ref().is_some_and(|path| modified(&autosave) > modified(path)) {
+        Some((Prompt::Recover, String::new()))
+    } else {
+        None
+    };
+    let mut autosaved = Instant::now();
+    // An action to run once the ship is unlocked, after asking about unsaved edits
+    let mut pending: Option<Action> = None;
+    let palette = panels::palette_entries(&block_registry);
+    let palette_images: BTreeMap<String, Image> = block_kinds.iter().map(|(name, image)| (name.clone(), render::scale(image, (PALETTE_ROW - 2) as u32))).collect();
+    let mut palette_hover = None;
+    // Hull is the usual brush for filling in a ship
+    let mut brush = palette.iter().position(|entry| entry.kind == "Hull").unwrap_or(0);
+    let mut selection: BTreeSet<usize> = BTreeSet::new();
+    let mut field: Option<Field> = None;
+    let mut left_button = false;
+    let mut running = true;
+    // Set when something only the editor knows about changes, such as the camera.
+    // A new snapshot is drawn anyway.
+    let mut redraw = true;
+    let mut drawn = 0;
 
-    while running.load(Ordering::SeqCst) {
+    while running {
         {
-            let mut ship = ship_lock.lock().unwrap();
+            for sample in engine.samples() {
+                telemetry.push(sample);
+            }
+            for err in engine.errors() {
+                eprintln!("{}", err);
+            }
+            let latest = engine.snapshot();
+            if latest.generation > snapshot.generation {
+                snapshot = latest;
+            }
 
-            if redraw.load(Ordering::SeqCst) {
-                redraw.store(false, Ordering::SeqCst);
+            if redraw || snapshot.generation != drawn {
+                redraw = false;
+                drawn = snapshot.generation;
 
+                let ship = snapshot.ship.clone();
                 let window_w = window.width();
-                //let window_h = window.height();
+                let window_h = window.height();
 
                 let deck = &ship.decks[ship.current_deck];
 
-                window.set(Color::rgb(255, 255, 255));
+                let size = camera.size();
+                let px = |n: i32| render::tile_px(n, size);
 
-                let title_x = (window_w - title.width()) as i32/2;
-                title.draw(&mut window, title_x, 0, Color::rgb(0, 0, 0));
+                if scaled_size != size {
+                    scaled_size = size;
+                    scaled_kinds = block_kinds.iter().map(|(name, image)| (name.clone(), render::scale(image, size as u32))).collect();
+                }
 
-                window.rect(0, 26, window_w, 2, Color::rgb(0, 0, 0));
+                // The deck is rendered off screen by the same code that exports it to files
+                let mut view = Image::from_color(window_w, window_h, Color::rgb(255, 255, 255));
+                render::draw_deck(&mut view, deck, &block_registry, &camera, &scaled_kinds, &overlay, snapshot.flows.get(ship.current_deck));
+                view.draw(&mut window, 0, 0);
 
-                for block in deck.blocks.iter() {
-                    let x = block.x as i32 * 32;
-                    let y = block.y as i32 * 32 + 32;
-                    if let Some(image) = block_kinds.get(&block.kind) {
-                        image.draw(&mut window, x, y);
-                    } else {
-                        window.rect(x, y, 32, 32, Color::rgb(128, 128, 128));
-                        font.render(&block.kind, 16.0).draw(&mut window, x, y, Color::rgb(0, 0, 0));
-                    }
+                // Label blocks without an image by kind
+                for block in deck.blocks.iter().filter(|block| ! scaled_kinds.contains_key(&block.kind)) {
+                    let (x, y) = camera.to_window(block.x, block.y);
+                    font.render(&block.kind, 16.0).draw(&mut window, x, y, Color::rgb(0, 0, 0));
+                }
+
+                let rooms = deck.rooms(&block_registry);
 
-                    if show_info {
-                        let mut info_rect = |x: i32, y: i32, name: &str, resource: &BlockResource| {
-                            let color = match name {
-                                "air" => Color::rgb(0, 255, 0),
-                                "electricity" => Color::rgb(255, 255, 0),
-                                "free_air" => Color::rgb(0, 255, 255),
-                                "fuel" => Color::rgb(255, 0, 0),
-                                "water" => Color::rgb(0, 0, 255),
-                                _ => Color::rgb(255, 0, 255)
-                            };
-                            let dy = (28.0 * resource.amount/resource.capacity) as i32;
-                            window.rect(x, y + 28 - dy, 4, dy as u32, color);
-                        };
-
-                        for (i, (name, resource)) in block.resources.iter().enumerate() {
-                            info_rect(x + 2 + i as i32 * 6, y + 2, name.borrow(), resource);
+                // Consoles flash while an alert reaches them
+                if start.elapsed().subsec_nanos() < 500000000 {
+                    for (i, block) in deck.blocks.iter().enumerate() {
+                        if block_registry.get(&block.kind).is_some_and(|kind| kind.has_tag("console")) && deck.signaled(i) {
+                            let (x, y) = camera.to_window(block.x, block.y);
+                            outline(&mut window, x, y, size, px(2), Color::rgb(255, 128, 0));
                         }
                     }
                 }
 
-                if let Some(i) = dragging {
-                    if let Some(block) = deck.blocks.get(i) {
-                        let x = block.x as i32 * 32;
-                        let y = block.y as i32 * 32 + 32;
+                match drag {
+                    Some(Drag::Band { start, end }) => {
+                        let (x, y) = camera.to_window(start.0.min(end.0), start.1.min(end.1));
+                        let w = ((start.0 - end.0).abs() as i32 + 1) * size;
+                        let h = ((start.1 - end.1).abs() as i32 + 1) * size;
+                        window.rect(x, y, w as u32, 1, Color::rgb(255, 0, 255));
+                        window.rect(x, y + h - 1, w as u32, 1, Color::rgb(255, 0, 255));
+                        window.rect(x, y, 1, h as u32, Color::rgb(255, 0, 255));
+                        window.rect(x + w - 1, y, 1, h as u32, Color::rgb(255, 0, 255));
+                    },
+                    Some(Drag::Draw { start, end }) => {
+                        let tiles = if tool == Tool::Line { selection::line_tiles(start, end) } else { selection::rect_tiles(start, end) };
+                        for (tile_x, tile_y) in tiles {
+                            let (x, y) = camera.to_window(tile_x, tile_y);
+                            outline(&mut window, x, y, size, px(2), Color::rgb(0, 192, 0));
+                        }
+                    },
+                    _ => ()
+                }
 
-                        window.rect(x, y, 32, 2, Color::rgb(255, 0, 0));
-                        window.rect(x, y, 2, 32, Color::rgb(255, 0, 0));
-                        window.rect(x, y + 30, 32, 2, Color::rgb(255, 0, 0));
-                        window.rect(x + 30, y, 2, 32, Color::rgb(255, 0, 0));
+                // Preview the blueprint under the mouse, in red where it would overlap other blocks
+                if let (Tool::Stamp, Some((hover_x, hover_y))) = (tool, hover) {
+                    if let Some(stamp) = blueprints.blueprints.values().nth(blueprint) {
+                        let collisions = stamp.collisions(deck, hover_x, hover_y);
+                        for block in stamp.blocks.iter() {
+                            let tile = (hover_x + block.x, hover_y + block.y);
+                            let (x, y) = camera.to_window(tile.0, tile.1);
+                            let color = if collisions.contains(&tile) { Color::rgb(255, 0, 0) } else { Color::rgb(0, 192, 0) };
+                            outline(&mut window, x, y, size, px(2), color);
+                        }
                     }
                 }
 
                 if let Some((block_x, block_y)) = editing {
-                    let x = block_x as i32 * 32;
-                    let y = block_y as i32 * 32 + 32;
+                    let (x, y) = camera.to_window(block_x, block_y);
+                    outline(&mut window, x, y, size, px(2), Color::rgb(0, 0, 255));
+                }
+
+                for block in selection.iter().filter_map(|&i| deck.blocks.get(i)) {
+                    let (x, y) = camera.to_window(block.x, block.y);
+                    outline(&mut window, x + px(2), y + px(2), size - 2 * px(2), px(2), Color::rgb(255, 0, 255));
+                }
+
+                // The inspector shows a single selected block
+                let inspected = if selection.len() == 1 {
+                    selection.iter().next().and_then(|&i| deck.blocks.get(i))
+                } else {
+                    None
+                };
+
+                if show_palette {
+                    if show_blueprints {
+                        panels::draw_blueprints(&mut window, &font, &blueprints, palette_hover, if tool == Tool::Stamp { Some(blueprint) } else { None });
+                    } else {
+                        panels::draw_palette(&mut window, &font, &palette, &palette_images, &block_registry, palette_hover, brush);
+                    }
+                }
+
+                if let Some(block) = inspected {
+                    panels::draw_inspector(&mut window, &font, block, field.as_ref());
+                }
+
+                // Graphs of the current deck and each watch on it, above the legend
+                if show_graphs {
+                    let mut sources = vec![(deck.name.clone(), Source::Deck(ship.current_deck))];
+                    for (i, watch) in telemetry.watches.iter().enumerate().filter(|&(_, watch)| watch.deck == ship.current_deck) {
+                        sources.push((watch.name.clone(), Source::Watch(i)));
+                    }
+                    let graphs_x = if show_palette { PALETTE_W } else { 0 };
+                    let graphs_w = window_w as i32 - graphs_x - if inspected.is_some() { INSPECTOR_W } else { 0 };
+                    let graphs_h = (window_h as i32 - 48 - PANEL_Y)/GRAPH_H;
+                    let shown = sources.len().min(graphs_h.max(0) as usize);
+                    panels::draw_graphs(&mut window, &font, &telemetry, &sources[sources.len() - shown ..], graphs_x, window_h as i32 - 48, graphs_w);
+                }
+
+                // The header is drawn over the deck, which may be panned beneath it
+                window.rect(0, 0, window_w, 28, Color::rgb(255, 255, 255));
+
+                // A star marks unsaved edits
+                let file = replay_path.as_ref().or(ship_path.as_ref()).and_then(|path| Path::new(path).file_name()).map_or("untitled".into(), |name| name.to_string_lossy());
+                let text = format!("{}{} - {} - {} ({})", ship.name, if snapshot.dirty { "*" } else { "" }, ship.current_deck, deck.name, file);
+                if text != title_text {
+                    title = font.render(&text, 24.0);
+                    title_text = text;
+                }
+                let title_x = (window_w - title.width()) as i32/2;
+                title.draw(&mut window, title_x, 0, Color::rgb(0, 0, 0));
+
+                window.rect(0, 26, window_w, 2, Color::rgb(0, 0, 0));
 
-                    window.rect(x, y, 32, 2, Color::rgb(0, 0, 255));
-                    window.rect(x, y, 2, 32, Color::rgb(0, 0, 255));
-                    window.rect(x, y + 30, 32, 2, Color::rgb(0, 0, 255));
-                    window.rect(x + 30, y, 2, 32, Color::rgb(0, 0, 255));
+                {
+                    let tool_name = match tool {
+                        Tool::Select => String::new(),
+                        Tool::Fill => format!(" fill {}", palette[brush].kind),
+                        Tool::Line => format!(" line {}", palette[brush].kind),
+                        Tool::Stamp => format!(" stamp {}", blueprints.blueprints.keys().nth(blueprint).map_or("", |name| name.as_str()))
+                    };
+                    let mode = match snapshot.replay {
+                        Some(ref replay) => format!(" replay {}/{}{}{}", replay.tick, replay.ticks, match replay.matches_end {
+                            Some(true) => " matches",
+                            Some(false) => " differs",
+                            None => ""
+                        }, replay.error.as_ref().map_or(String::new(), |err| format!(" stopped: {}", err))),
+                        None => if snapshot.recording { " recording".to_string() } else { String::new() }
+                    };
+                    let status = format!("{}x{}{} {}px {}{}", snapshot.speed, if snapshot.paused { " paused" } else { "" }, mode, size, overlay.name(), tool_name);
+                    font.render(&status, 16.0).draw(&mut window, 4, 4, Color::rgb(0, 0, 0));
+                }
+
+                if let Some(legend) = render::legend(&overlay) {
+                    legend.draw(&mut window, if show_palette { PALETTE_W } else { 0 }, window_h as i32 - 46);
+                }
+
+                if let Some((ref kind, ref text)) = prompt {
+                    let line = font.render(&match *kind {
+                        Prompt::BlueprintName => format!("Blueprint name: {}_", text),
+                        Prompt::DeckName => format!("Deck name: {}_", text),
+                        Prompt::DeleteDeck => format!("Delete {} and all of its blocks? Y/N", deck.name),
+                        Prompt::SaveAs => format!("Save as: {}_", text),
+                        Prompt::Open => format!("Open: {}_", text),
+                        Prompt::Discard(_) => format!("Discard unsaved edits to {}? Y/N", ship.name),
+                        Prompt::Recover => format!("Recover unsaved edits from {}? Y/N", autosave_path(&ship_path, &res))
+                    }, 16.0);
+                    let line_x = if show_palette { PALETTE_W } else { 0 };
+                    let line_y = window.height() as i32 - 20;
+                    window.rect(line_x, line_y - 2, line.width() + 8, 22, Color::rgb(255, 255, 192));
+                    line.draw(&mut window, line_x + 4, line_y, Color::rgb(0, 0, 0));
+                } else if let Some((hover_x, hover_y)) = hover {
+                    // Summarize the room under the mouse
+                    if let Some(room) = rooms.iter().find(|room| room.contains(hover_x, hover_y)) {
+                        let text = format!("Room: {} tiles, free_air {:.1}/{:.1} ({:.0}%){}",
+                                           room.cells.len(), room.free_air, room.volume, room.pressure() * 100.0,
+                                           if room.breached { ", breached" } else { "" });
+                        let summary = font.render(&text, 16.0);
+                        let summary_x = if show_palette { PALETTE_W } else { 0 };
+                        let summary_y = window.height() as i32 - 20;
+                        window.rect(summary_x, summary_y - 2, summary.width() + 8, 22, Color::rgb(255, 255, 255));
+                        summary.draw(&mut window, summary_x + 4, summary_y, if room.breached { Color::rgb(255, 0, 0) } else { Color::rgb(0, 0, 0) });
+                    }
+                }
+
+                let alerts = ship.alerts();
+                if ! alerts.is_empty() {
+                    let panel_w = 320;
+                    let panel_x = window_w as i32 - panel_w as i32 - if inspected.is_some() { INSPECTOR_W } else { 0 };
+                    let panel_h = 24 + alerts.len() as u32 * 18;
+
+                    window.rect(panel_x, 28, panel_w, panel_h, Color::rgb(255, 255, 255));
+                    window.rect(panel_x, 28, 2, panel_h, Color::rgb(0, 0, 0));
+                    window.rect(panel_x, 28 + panel_h as i32, panel_w, 2, Color::rgb(0, 0, 0));
+                    font.render("Alerts", 16.0).draw(&mut window, panel_x + 6, 30, Color::rgb(255, 0, 0));
+
+                    for (i, &(d, ref alert)) in alerts.iter().enumerate() {
+                        let text = format!("{} {}, {}: {} {:.0}% < {:.0}%", ship.decks[d].name, alert.x, alert.y, alert.resource, alert.pressure * 100.0, alert.threshold * 100.0);
+                        font.render(&text, 16.0).draw(&mut window, panel_x + 6, 48 + i as i32 * 18, Color::rgb(0, 0, 0));
+                    }
                 }
 
                 window.sync();
             }
 
-            let mut deck_i = ship.current_deck;
-            let mut reload = false;
-            let mut save = false;
+            let mut deck_i = snapshot.ship.current_deck;
+            // Path to save the ship to
+            let mut save = None;
             {
-                let deck_len = ship.decks.len();
-                let deck = &mut ship.decks[deck_i];
-
+                let (window_w, window_h) = (window.width(), window.height());
                 let mut evented = true;
                 while evented {
                     evented = false;
@@ -154,180 +639,704 @@ fn main(){
                     for event in window.events() {
                         evented = true;
 
+                        // Switch to a deck chosen by an earlier event first, so this event edits the deck it is drawn on
+                        if set_deck(&engine, &mut snapshot, &mut deck_i) {
+                            drag = None;
+                            selection.clear();
+                            field = None;
+                            redraw = true;
+                        }
+
+                        // The ship as this event found it, and the index of the deck being edited, which is the one drawn
+                        let ship = snapshot.ship.clone();
+                        let deck = ship.current_deck;
+
                         match event.to_option() {
-                            EventOption::Key(key_event) => if key_event.pressed {
+                            EventOption::Key(key_event) => if key_event.scancode == K_CTRL {
+                                ctrl = key_event.pressed;
+                            } else if key_event.scancode == K_LEFT_SHIFT || key_event.scancode == K_RIGHT_SHIFT {
+                                shift = key_event.pressed;
+                            } else if key_event.pressed && replaying {
+                                // A replay can be viewed and scrubbed, but not edited
+                                let tick = snapshot.replay.as_ref().map_or(0, |replay| replay.tick);
                                 match key_event.scancode {
-                                    K_UP => if deck_i + 1 < deck_len {
+                                    K_PGUP => if deck_i + 1 < ship.decks.len() {
                                         deck_i += 1;
                                     },
-                                    K_DOWN => if deck_i > 0 {
+                                    K_PGDN => if deck_i > 0 {
                                         deck_i -= 1;
                                     },
-                                    K_DEL => if let Some((block_x, block_y)) = editing.take() {
-                                        let mut remove = None;
-                                        for (i, block) in deck.blocks.iter().enumerate() {
-                                            if block_x == block.x && block_y == block.y {
-                                                remove = Some(i);
+                                    K_UP => camera.pan(0, PAN_STEP),
+                                    K_DOWN => camera.pan(0, -PAN_STEP),
+                                    K_LEFT => camera.pan(PAN_STEP, 0),
+                                    K_RIGHT => camera.pan(-PAN_STEP, 0),
+                                    K_HOME => fit(&mut camera, &ship, if show_palette { PALETTE_W } else { 0 }, window_w, window_h),
+                                    K_F1 => overlay = overlay.next(),
+                                    K_F2 => overlay = Overlay::None,
+                                    K_F11 => show_graphs = ! show_graphs,
+                                    _ => match key_event.character {
+                                        ' ' => {
+                                            send(&engine, Command::TogglePause);
+                                        },
+                                        '+' | '=' => {
+                                            send(&engine, Command::Faster);
+                                        },
+                                        '-' => {
+                                            send(&engine, Command::Slower);
+                                        },
+                                        '[' => camera.zoom_out(mouse.0, mouse.1),
+                                        ']' => camera.zoom_in(mouse.0, mouse.1),
+                                        // Step or skip forward and backward, pausing playback
+                                        ',' | '.' | '<' | '>' => {
+                                            let target = match key_event.character {
+                                                ',' => tick.saturating_sub(1),
+                                                '.' => tick + 1,
+                                                '<' => tick.saturating_sub(SKIP_TICKS),
+                                                _ => tick + SKIP_TICKS
+                                            };
+                                            // Totals recorded after the new tick are in the future
+                                            if target < tick {
+                                                telemetry.clear();
+                                            }
+                                            send(&engine, Command::Seek(target));
+                                        },
+                                        _ => ()
+                                    }
+                                }
+                                redraw = true;
+                            } else if key_event.pressed && prompt.is_some() {
+                                // Typing at the prompt, which is closed by Enter or Esc
+                                if let Some((kind, mut text)) = prompt.take() {
+                                    match (kind, key_event.scancode) {
+                                        // Any key but Y cancels
+                                        (Prompt::DeleteDeck, _) => if key_event.character == 'y' || key_event.character == 'Y' {
+                                            if call(&engine, &mut snapshot, Command::Edit(Edit::RemoveDeck { index: deck })) {
+                                                deck_i = snapshot.ship.current_deck;
+                                                drag = None;
+                                                selection.clear();
                                             }
+                                        },
+                                        (Prompt::Discard(action), _) => if key_event.character == 'y' || key_event.character == 'Y' {
+                                            pending = Some(action);
+                                        },
+                                        (Prompt::Recover, _) => if key_event.character == 'y' || key_event.character == 'Y' {
+                                            pending = Some(Action::Recover);
+                                        },
+                                        (_, K_ENTER) if text.is_empty() => eprintln!("a name is needed"),
+                                        (Prompt::SaveAs, K_ENTER) => save = Some(text),
+                                        (Prompt::Open, K_ENTER) => request(Action::Open(text), snapshot.dirty, &mut prompt, &mut pending),
+                                        (Prompt::DeckName, K_ENTER) => {
+                                            call(&engine, &mut snapshot, Command::Edit(Edit::RenameDeck { index: deck, name: text }));
+                                        },
+                                        (Prompt::BlueprintName, K_ENTER) => {
+                                            let indexes: Vec<usize> = selection.iter().cloned().collect();
+                                            let new_blueprint = Blueprint::extract(text.clone(), &ship.decks[deck], &indexes);
+                                            match blueprints.save(&blueprints_dir, new_blueprint) {
+                                                Ok(()) => {
+                                                    println!("Saved blueprint {}", text);
+                                                    blueprint = blueprints.blueprints.keys().position(|key| *key == text).unwrap_or(0);
+                                                },
+                                                Err(err) => eprintln!("{}: {}", blueprints_dir.display(), err)
+                                            }
+                                        },
+                                        (_, K_ESC) => (),
+                                        (kind, K_BKSP) => {
+                                            text.pop();
+                                            prompt = Some((kind, text));
+                                        },
+                                        (kind, _) => {
+                                            let c = key_event.character;
+                                            if c != '\0' && ! c.is_control() {
+                                                text.push(c);
+                                            }
+                                            prompt = Some((kind, text));
                                         }
-                                        if let Some(i) = remove {
-                                            deck.blocks.remove(i);
-                                            redraw.store(true, Ordering::SeqCst);
+                                    }
+                                }
+                                redraw = true;
+                            } else if key_event.pressed && field.is_some() {
+                                // Typing into an inspector field
+                                match key_event.scancode {
+                                    K_ENTER => if let Some(field) = field.take() {
+                                        if let Err(err) = commit(&engine, &mut snapshot, deck, &field) {
+                                            eprintln!("{}", err);
                                         }
                                     },
-                                    K_ESC => if editing.take().is_some() {
-                                        redraw.store(true, Ordering::SeqCst);
+                                    K_ESC => field = None,
+                                    K_BKSP => if let Some(ref mut field) = field {
+                                        field.text.pop();
                                     },
-                                    K_F1 => if ! show_info {
-                                        show_info = true;
-                                        redraw.store(true, Ordering::SeqCst);
+                                    _ => if let Some(ref mut field) = field {
+                                        let c = key_event.character;
+                                        if c.is_digit(10) || c == '.' || c == 'e' {
+                                            field.text.push(c);
+                                        }
+                                    }
+                                }
+                                redraw = true;
+                            } else if key_event.pressed {
+                                match key_event.scancode {
+                                    // Shift inserts an empty deck above or below, and Ctrl moves the deck up or down
+                                    K_PGUP if shift => {
+                                        let new_deck = Deck::new(format!("Deck {}", ship.decks.len() + 1), vec![]);
+                                        if call(&engine, &mut snapshot, Command::Edit(Edit::AddDeck { index: deck + 1, deck: new_deck })) {
+                                            deck_i = deck + 1;
+                                        }
                                     },
-                                    K_F2 => if show_info {
-                                        show_info = false;
-                                        redraw.store(true, Ordering::SeqCst);
+                                    K_PGDN if shift => {
+                                        let new_deck = Deck::new(format!("Deck {}", ship.decks.len() + 1), vec![]);
+                                        if call(&engine, &mut snapshot, Command::Edit(Edit::AddDeck { index: deck, deck: new_deck })) {
+                                            deck_i = deck;
+                                        }
                                     },
-                                    K_F5 => reload = true,
-                                    K_F6 => save = true,
-                                    _ => {
-                                        match key_event.character {
-                                            'A' | 'a' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Tank".to_string());
-                                                block.resources.insert("air".into(), BlockResource { amount: 100.0, capacity: 100.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'E' | 'e' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Tank".to_string());
-                                                block.resources.insert("electricity".into(), BlockResource { amount: 100.0, capacity: 100.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'F' | 'f' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Tank".to_string());
-                                                block.resources.insert("fuel".into(), BlockResource { amount: 100.0, capacity: 100.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'W' | 'w' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Tank".to_string());
-                                                block.resources.insert("water".into(), BlockResource { amount: 100.0, capacity: 100.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'C' | 'c' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Conduit".to_string());
-                                                block.resources.insert("air".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                block.resources.insert("electricity".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                block.resources.insert("fuel".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                block.resources.insert("water".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'D' | 'd' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Deck".to_string());
-                                                block.resources.insert("free_air".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'H' | 'h' => if let Some((block_x, block_y)) = editing.take() {
-                                                deck.blocks.push(Block::new(block_x, block_y, "Hull".to_string()));
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'M' | 'm' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Man".to_string());
-                                                block.resources.insert("free_air".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            'V' | 'v' => if let Some((block_x, block_y)) = editing.take() {
-                                                let mut block = Block::new(block_x, block_y, "Vent".to_string());
-                                                block.resources.insert("air".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                block.resources.insert("free_air".into(), BlockResource { amount: 0.0, capacity: 5.0 });
-                                                deck.blocks.push(block);
-                                                redraw.store(true, Ordering::SeqCst);
-                                            },
-                                            _ => ()
+                                    K_PGUP if ctrl => if deck + 1 < ship.decks.len() {
+                                        call(&engine, &mut snapshot, Command::Edit(Edit::MoveDeck { from: deck, to: deck + 1 }));
+                                        deck_i = snapshot.ship.current_deck;
+                                        redraw = true;
+                                    },
+                                    K_PGDN if ctrl => if deck > 0 {
+                                        call(&engine, &mut snapshot, Command::Edit(Edit::MoveDeck { from: deck, to: deck - 1 }));
+                                        deck_i = snapshot.ship.current_deck;
+                                        redraw = true;
+                                    },
+                                    K_PGUP => if deck_i + 1 < ship.decks.len() {
+                                        deck_i += 1;
+                                    },
+                                    K_PGDN => if deck_i > 0 {
+                                        deck_i -= 1;
+                                    },
+                                    K_UP => {
+                                        camera.pan(0, PAN_STEP);
+                                        redraw = true;
+                                    },
+                                    K_DOWN => {
+                                        camera.pan(0, -PAN_STEP);
+                                        redraw = true;
+                                    },
+                                    K_LEFT => {
+                                        camera.pan(PAN_STEP, 0);
+                                        redraw = true;
+                                    },
+                                    K_RIGHT => {
+                                        camera.pan(-PAN_STEP, 0);
+                                        redraw = true;
+                                    },
+                                    K_HOME => {
+                                        fit(&mut camera, &ship, if show_palette { PALETTE_W } else { 0 }, window_w, window_h);
+                                        redraw = true;
+                                    },
+                                    K_DEL if shift => {
+                                        prompt = Some((Prompt::DeleteDeck, String::new()));
+                                        redraw = true;
+                                    },
+                                    K_DEL => if let Some((block_x, block_y)) = editing.take() {
+                                        if let Some(i) = ship.decks[deck].block_at(block_x, block_y) {
+                                            call(&engine, &mut snapshot, Command::Edit(Edit::RemoveBlock { deck: deck, index: i }));
+                                            selection.clear();
+                                            redraw = true;
+                                        }
+                                    } else if ! selection.is_empty() {
+                                        let indexes: Vec<usize> = selection.iter().cloned().collect();
+                                        call(&engine, &mut snapshot, Command::Edit(Edit::remove_blocks(deck, &indexes)));
+                                        selection.clear();
+                                        redraw = true;
+                                    },
+                                    K_ESC => if tool != Tool::Select {
+                                        tool = Tool::Select;
+                                        redraw = true;
+                                    } else if editing.take().is_some() || ! selection.is_empty() {
+                                        selection.clear();
+                                        redraw = true;
+                                    },
+                                    K_F1 => {
+                                        overlay = overlay.next();
+                                        redraw = true;
+                                    },
+                                    K_F2 => if overlay != Overlay::None {
+                                        overlay = Overlay::None;
+                                        redraw = true;
+                                    },
+                                    K_F3 => {
+                                        show_palette = ! show_palette || show_blueprints;
+                                        show_blueprints = false;
+                                        redraw = true;
+                                    },
+                                    K_F4 => {
+                                        show_palette = ! show_palette || ! show_blueprints;
+                                        show_blueprints = show_palette;
+                                        redraw = true;
+                                    },
+                                    K_F5 => request(Action::Reload, snapshot.dirty, &mut prompt, &mut pending),
+                                    K_F9 => {
+                                        prompt = Some((Prompt::DeckName, ship.decks[deck].name.clone()));
+                                        redraw = true;
+                                    },
+                                    K_F11 => {
+                                        show_graphs = ! show_graphs;
+                                        redraw = true;
+                                    },
+                                    K_F10 => if call(&engine, &mut snapshot, Command::Edit(Edit::DuplicateDeck { index: deck })) {
+                                        deck_i = deck + 1;
+                                    },
+                                    K_F7 => {
+                                        tool = if tool == Tool::Fill { Tool::Select } else { Tool::Fill };
+                                        redraw = true;
+                                    },
+                                    K_F8 => {
+                                        tool = if tool == Tool::Line { Tool::Select } else { Tool::Line };
+                                        redraw = true;
+                                    },
+                                    K_F6 => match ship_path {
+                                        Some(ref path) => save = Some(path.clone()),
+                                        None => prompt = Some((Prompt::SaveAs, res.join("").to_string_lossy().into_owned()))
+                                    },
+                                    _ => match key_event.character {
+                                        'z' | 'Z' if ctrl => {
+                                            if snapshot.can_undo {
+                                                call(&engine, &mut snapshot, Command::Undo);
+                                            }
+                                            drag = None;
+                                            selection.clear();
+                                            field = None;
+                                            deck_i = snapshot.ship.current_deck;
+                                            redraw = true;
+                                        },
+                                        'y' | 'Y' if ctrl => {
+                                            if snapshot.can_redo {
+                                                call(&engine, &mut snapshot, Command::Redo);
+                                            }
+                                            drag = None;
+                                            selection.clear();
+                                            field = None;
+                                            deck_i = snapshot.ship.current_deck;
+                                            redraw = true;
+                                        },
+                                        'c' | 'C' | 'x' | 'X' if ctrl => if ! selection.is_empty() {
+                                            let indexes: Vec<usize> = selection.iter().cloned().collect();
+                                            clipboard = ship.decks[deck].copy_blocks(&indexes);
+                                            if key_event.character == 'x' || key_event.character == 'X' {
+                                                call(&engine, &mut snapshot, Command::Edit(Edit::remove_blocks(deck, &indexes)));
+                                                selection.clear();
+                                                redraw = true;
+                                            }
+                                        },
+                                        // Paste with the top left of the copied blocks at the tile being edited, or under the mouse
+                                        'v' | 'V' if ctrl => if let Some((paste_x, paste_y)) = editing.or(hover) {
+                                            let count = ship.decks[deck].blocks.len();
+                                            let blocks = clipboard.iter().map(|block| {
+                                                let mut block = block.clone();
+                                                block.x += paste_x;
+                                                block.y += paste_y;
+                                                block
+                                            }).collect::<Vec<_>>();
+                                            let pasted = blocks.len();
+                                            if call(&engine, &mut snapshot, Command::Edit(Edit::add_blocks(deck, count, blocks))) {
+                                                selection = (count .. count + pasted).collect();
+                                            }
+                                            editing = None;
+                                            field = None;
+                                            redraw = true;
+                                        },
+                                        // Ctrl+S saves, and Ctrl+Shift+S or Ctrl+S on a new ship asks where
+                                        's' | 'S' if ctrl => match ship_path {
+                                            Some(ref path) if ! shift => save = Some(path.clone()),
+                                            _ => {
+                                                let path = ship_path.clone().unwrap_or_else(|| res.join("").to_string_lossy().into_owned());
+                                                prompt = Some((Prompt::SaveAs, path));
+                                                redraw = true;
+                                            }
+                                        },
+                                        'o' | 'O' if ctrl => {
+                                            prompt = Some((Prompt::Open, res.join("").to_string_lossy().into_owned()));
+                                            redraw = true;
+                                        },
+                                        'n' | 'N' if ctrl => {
+                                            request(Action::New, snapshot.dirty, &mut prompt, &mut pending);
+                                            redraw = true;
+                                        },
+                                        'b' | 'B' if ctrl => if ! selection.is_empty() {
+                                            prompt = Some((Prompt::BlueprintName, String::new()));
+                                            redraw = true;
+                                        },
+                                        // Ctrl+W records the totals of the selection, or of the room under the mouse,
+                                        // and Ctrl+Shift+W stops recording every watch on this deck
+                                        'w' | 'W' if ctrl => {
+                                            if shift {
+                                                let mut i = telemetry.watches.len();
+                                                while i > 0 {
+                                                    i -= 1;
+                                                    if telemetry.watches[i].deck == deck {
+                                                        telemetry.unwatch(i);
+                                                    }
+                                                }
+                                            } else if ! selection.is_empty() {
+                                                let tiles = selection.iter().filter_map(|&i| ship.decks[deck].blocks.get(i)).map(|block| (block.x, block.y)).collect();
+                                                let name = format!("Selection of {} blocks", selection.len());
+                                                telemetry.watch(Watch::new(name, deck, tiles));
+                                                show_graphs = true;
+                                            } else if let Some((hover_x, hover_y)) = hover {
+                                                if let Some(room) = ship.decks[deck].rooms(&block_registry).into_iter().find(|room| room.contains(hover_x, hover_y)) {
+                                                    let name = format!("Room at {}, {}", hover_x, hover_y);
+                                                    telemetry.watch(Watch::new(name, deck, room.cells));
+                                                    show_graphs = true;
+                                                }
+                                            }
+                                            send(&engine, Command::Watches(telemetry.watches.clone()));
+                                            redraw = true;
+                                        },
+                                        'e' | 'E' if ctrl => {
+                                            let path = telemetry_path(&ship_path, &res);
+                                            match telemetry.save_csv(&path) {
+                                                Ok(()) => println!("Exported {}", path),
+                                                Err(err) => eprintln!("{}: {}", path, err)
+                                            }
+                                        },
+                                        'a' | 'A' if ctrl => {
+                                            selection = (0 .. ship.decks[deck].blocks.len()).collect();
+                                            field = None;
+                                            redraw = true;
+                                        },
+                                        'r' | 'R' if ctrl => {
+                                            let rotation = if shift { Transform::RotateCounterClockwise } else { Transform::RotateClockwise };
+                                            transform(&engine, &mut snapshot, deck, &selection, rotation);
+                                            redraw = true;
+                                        },
+                                        'm' | 'M' if ctrl => {
+                                            let mirror = if shift { Transform::MirrorY } else { Transform::MirrorX };
+                                            transform(&engine, &mut snapshot, deck, &selection, mirror);
+                                            redraw = true;
+                                        },
+                                        '[' => {
+                                            camera.zoom_out(mouse.0, mouse.1);
+                                            redraw = true;
+                                        },
+                                        ']' => {
+                                            camera.zoom_in(mouse.0, mouse.1);
+                                            redraw = true;
+                                        },
+                                        ' ' => {
+                                            send(&engine, Command::TogglePause);
+                                            redraw = true;
+                                        },
+                                        '.' => {
+                                            send(&engine, Command::Step);
+                                            redraw = true;
+                                        },
+                                        '+' | '=' => {
+                                            send(&engine, Command::Faster);
+                                            redraw = true;
+                                        },
+                                        '-' => {
+                                            send(&engine, Command::Slower);
+                                            redraw = true;
+                                        },
+                                        c => if let Some((block_x, block_y)) = editing {
+                                            if let Some((kind, variant)) = block_registry.hotkey(c) {
+                                                place(&engine, &mut snapshot, &block_registry, deck, block_x, block_y, kind, variant);
+                                                editing = None;
+                                                redraw = true;
+                                            }
                                         }
                                     }
                                 }
                             },
                             EventOption::Mouse(mouse_event) => {
-                                if mouse_event.left_button {
-                                    if let Some(i) = dragging {
-                                        let x = max(mouse_event.x/32, 0) as usize;
-                                        let y = max((mouse_event.y - 32)/32, 0) as usize;
-
-                                        let block = &mut deck.blocks[i];
-                                        if block.x != x || block.y != y {
-                                            block.x = x;
-                                            block.y = y;
-                                            redraw.store(true, Ordering::SeqCst);
-                                        }
-                                    } else {
-                                        println!("Left {}, {}", mouse_event.x, mouse_event.y);
-
-                                        for (i, block) in deck.blocks.iter().enumerate() {
-                                            let x = block.x as i32 * 32;
-                                            let y = block.y as i32 * 32 + 32;
-                                            if mouse_event.x >= x && mouse_event.x < x + 32 && mouse_event.y >= y && mouse_event.y < y + 32 {
-                                                dragging = Some(i);
-                                                redraw.store(true, Ordering::SeqCst);
-                                                println!("    {:?}", block);
+                                let inspecting = selection.len() == 1;
+                                let in_palette = show_palette && mouse_event.x < PALETTE_W;
+                                let in_inspector = inspecting && mouse_event.x >= window_w as i32 - INSPECTOR_W;
+                                // The header hides the deck above PANEL_Y
+                                let in_view = mouse_event.y >= PANEL_Y && ! in_palette && ! in_inspector;
+                                // Clicks do nothing in a replay, which cannot be edited
+                                let pressed = mouse_event.left_button && ! left_button && ! replaying;
+                                left_button = mouse_event.left_button;
+                                let tile = camera.to_tile(mouse_event.x, mouse_event.y);
+
+                                if show_palette && show_blueprints {
+                                    let entry = panels::palette_entry_at(mouse_event.x, mouse_event.y, blueprints.blueprints.len());
+                                    if palette_hover != entry {
+                                        palette_hover = entry;
+                                        redraw = true;
+                                    }
+
+                                    // Clicking a blueprint picks it for the stamp tool
+                                    if let (true, Some(i)) = (pressed, entry) {
+                                        blueprint = i;
+                                        tool = Tool::Stamp;
+                                        redraw = true;
+                                    }
+                                } else if show_palette {
+                                    let entry = panels::palette_entry_at(mouse_event.x, mouse_event.y, palette.len());
+                                    if palette_hover != entry {
+                                        palette_hover = entry;
+                                        redraw = true;
+                                    }
+
+                                    // Clicking an entry places it on the tile chosen with the right button, like its hotkey,
+                                    // or otherwise makes it the brush for the fill and line tools
+                                    if pressed {
+                                        if let Some(i) = entry {
+                                            match (palette[i].get(&block_registry), editing) {
+                                                (Some((kind, variant)), Some((block_x, block_y))) => {
+                                                    place(&engine, &mut snapshot, &block_registry, deck, block_x, block_y, kind, variant);
+                                                    editing = None;
+                                                },
+                                                _ => brush = i
                                             }
+                                            redraw = true;
                                         }
                                     }
-                                } else {
-                                    if dragging.take().is_some() {
-                                        redraw.store(true, Ordering::SeqCst);
+                                }
+
+                                if in_inspector && pressed {
+                                    if let Some(&i) = selection.iter().next() {
+                                        field = ship.decks[deck].blocks.get(i)
+                                            .and_then(|block| panels::inspector_field_at(mouse_event.x, mouse_event.y, window_w, block).map(|(resource, capacity)| {
+                                                let value = block.resources[resource.as_str()];
+                                                Field {
+                                                    block: i,
+                                                    text: format!("{}", if capacity { value.capacity } else { value.amount }),
+                                                    resource: resource,
+                                                    capacity: capacity
+                                                }
+                                            }));
+                                        redraw = true;
                                     }
                                 }
 
-                                if mouse_event.right_button {
-                                    let x = max(mouse_event.x/32, 0) as usize;
-                                    let y = max((mouse_event.y - 32)/32, 0) as usize;
+                                // Dragging with the middle button pans
+                                if mouse_event.middle_button {
+                                    if let Some((last_x, last_y)) = panning {
+                                        camera.pan(mouse_event.x - last_x, mouse_event.y - last_y);
+                                        redraw = true;
+                                    }
+                                    panning = Some((mouse_event.x, mouse_event.y));
+                                } else {
+                                    panning = None;
+                                }
+                                mouse = (mouse_event.x, mouse_event.y);
 
-                                    if editing != Some((x, y)) {
-                                        println!("Right {}, {}", mouse_event.x, mouse_event.y);
-                                        editing = Some((x, y));
-                                        redraw.store(true, Ordering::SeqCst);
+                                let hover_tile = if in_view {
+                                    Some(tile)
+                                } else {
+                                    None
+                                };
+                                if hover != hover_tile {
+                                    hover = hover_tile;
+                                    redraw = true;
+                                }
 
-                                        for block in deck.blocks.iter() {
-                                            let x = block.x as i32 * 32;
-                                            let y = block.y as i32 * 32 + 32;
-                                            if mouse_event.x >= x && mouse_event.x < x + 32 && mouse_event.y >= y && mouse_event.y < y + 32 {
-                                                println!("    {:?}", block);
+                                if pressed && in_view && tool == Tool::Stamp {
+                                    if let Some(stamp) = blueprints.blueprints.values().nth(blueprint) {
+                                        let count = ship.decks[deck].blocks.len();
+                                        match stamp.stamp(deck, &ship.decks[deck], tile.0, tile.1) {
+                                            Ok(edit) => if call(&engine, &mut snapshot, Command::Edit(edit)) {
+                                                selection = (count .. count + stamp.blocks.len()).collect();
+                                            },
+                                            Err(err) => eprintln!("{}", err)
+                                        }
+                                        field = None;
+                                        redraw = true;
+                                    }
+                                } else if pressed && in_view {
+                                    field = None;
+                                    drag = if tool != Tool::Select {
+                                        Some(Drag::Draw { start: tile, end: tile })
+                                    } else if let Some(i) = ship.decks[deck].block_at(tile.0, tile.1) {
+                                        if shift {
+                                            // Shift-clicking adds a block to the selection, or removes it
+                                            if ! selection.remove(&i) {
+                                                selection.insert(i);
+                                            }
+                                            None
+                                        } else {
+                                            // Grabbing a block that is not selected selects only it
+                                            if ! selection.contains(&i) {
+                                                selection.clear();
+                                                selection.insert(i);
                                             }
+                                            Some(Drag::Move {
+                                                grab: tile,
+                                                starts: selection.iter().map(|&j| (j, ship.decks[deck].blocks[j].x, ship.decks[deck].blocks[j].y)).collect(),
+                                                moved: false
+                                            })
+                                        }
+                                    } else {
+                                        if ! shift {
+                                            selection.clear();
                                         }
+                                        Some(Drag::Band { start: tile, end: tile })
+                                    };
+                                    redraw = true;
+                                } else if mouse_event.left_button {
+                                    match drag {
+                                        Some(Drag::Move { grab, ref starts, ref mut moved }) => {
+                                            let (dx, dy) = (tile.0 - grab.0, tile.1 - grab.1);
+                                            if dx != 0 || dy != 0 || *moved {
+                                                let moves: Vec<(usize, isize, isize)> = starts.iter().map(|&(i, x, y)| (i, x + dx, y + dy)).collect();
+                                                send(&engine, Command::Preview(Edit::move_blocks(deck, &moves)));
+                                                *moved = true;
+                                                redraw = true;
+                                            }
+                                        },
+                                        Some(Drag::Band { ref mut end, .. }) | Some(Drag::Draw { ref mut end, .. }) => if *end != tile {
+                                            *end = tile;
+                                            redraw = true;
+                                        },
+                                        None => ()
+                                    }
+                                } else if let Some(finished) = drag.take() {
+                                    let edit = match finished {
+                                        Drag::Move { grab, starts, moved: true } => {
+                                            // Put the blocks back, so the whole drag is undone as one move to where it was let go
+                                            let (dx, dy) = (tile.0 - grab.0, tile.1 - grab.1);
+                                            let moves: Vec<(usize, isize, isize)> = starts.iter().map(|&(i, x, y)| (i, x + dx, y + dy)).collect();
+                                            call(&engine, &mut snapshot, Command::CommitPreview {
+                                                revert: Edit::move_blocks(deck, &starts),
+                                                edit: Edit::move_blocks(deck, &moves)
+                                            });
+                                            None
+                                        },
+                                        Drag::Move { starts, moved: false, .. } => match starts.first() {
+                                            // Clicking a door without moving it opens or closes it
+                                            Some(&(i, _, _)) if starts.len() == 1 && ship.decks[deck].blocks[i].open.is_some() => Some(Edit::ToggleBlock { deck: deck, index: i }),
+                                            _ => None
+                                        },
+                                        Drag::Band { start, end } => {
+                                            selection.extend(ship.decks[deck].blocks_in(start, end));
+                                            None
+                                        },
+                                        Drag::Draw { start, end } => palette[brush].get(&block_registry).map(|(kind, variant)| {
+                                            let tiles = if tool == Tool::Line { selection::line_tiles(start, end) } else { selection::rect_tiles(start, end) };
+                                            let blocks = tiles.into_iter()
+                                                .filter(|&(x, y)| ! ship.decks[deck].has_kind_at(x, y, &kind.name))
+                                                .filter_map(|(x, y)| match variant {
+                                                    Some(variant) => Some(Block::from_variant(x, y, kind, variant)),
+                                                    None => Block::from_kind(x, y, &kind.name, &block_registry)
+                                                })
+                                                .collect();
+                                            Edit::add_blocks(deck, ship.decks[deck].blocks.len(), blocks)
+                                        })
+                                    };
+                                    if let Some(edit) = edit {
+                                        call(&engine, &mut snapshot, Command::Edit(edit));
+                                    }
+                                    redraw = true;
+                                }
+
+                                if mouse_event.right_button && in_view {
+                                    if editing != Some(tile) {
+                                        editing = Some(tile);
+                                        redraw = true;
                                     }
                                 }
                             },
-                            EventOption::Quit(_quit_event) => running.store(false, Ordering::SeqCst),
+                            EventOption::Quit(_quit_event) => {
+                                request(Action::Quit, snapshot.dirty, &mut prompt, &mut pending);
+                                redraw = true;
+                            },
                             _ => ()
                         }
                     }
                 }
             }
 
-            if deck_i != ship.current_deck {
-                ship.current_deck = deck_i;
-                title = font.render(&format!("{} - {} - {}", ship.name, ship.current_deck, ship.decks[ship.current_deck].name), 24.0);
-                redraw.store(true, Ordering::SeqCst);
+            if set_deck(&engine, &mut snapshot, &mut deck_i) {
+                drag = None;
+                selection.clear();
+                field = None;
+                redraw = true;
             }
 
-            if save {
-                println!("Save");
-                starship::save("res/ship.json", &*ship).unwrap();
+            if let Some(path) = save {
+                // Saved by the simulation thread, so the edits sent before are saved and no later ones
+                match engine.call(Command::Save(path.clone())) {
+                    Ok(latest) => {
+                        snapshot = latest;
+                        println!("Saved {}", path);
+                        let _ = fs::remove_file(autosave_path(&ship_path, &res));
+                        ship_path = Some(path);
+                    },
+                    Err(err) => eprintln!("{}: {}", path, err)
+                }
+                redraw = true;
             }
 
-            if reload {
-                println!("Reload");
-                *ship = starship::load("res/ship.json").unwrap();
-                redraw.store(true, Ordering::SeqCst);
+            // Autosave a minute after the first unsaved edit, and every minute after that
+            if ! snapshot.dirty {
+                autosaved = Instant::now();
+            } else if autosaved.elapsed() >= Duration::from_secs(AUTOSAVE_SECS) {
+                let path = autosave_path(&ship_path, &res);
+                if let Err(err) = starship::save_with_backups(&path, &snapshot.ship, 0) {
+                    eprintln!("{}: {}", path, err);
+                }
+                autosaved = Instant::now();
+            }
+
+            if let Some(action) = pending.take() {
+                let recovering = action == Action::Recover;
+                // Keep editing the current ship if the new one is broken
+                let (path, loaded) = match action {
+                    Action::Quit => {
+                        // Quitting cleanly discards the autosave, as the edits were saved or discarded
+                        let _ = fs::remove_file(autosave_path(&ship_path, &res));
+                        stop_recording(&engine, &mut snapshot, &mut record_path);
+                        running = false;
+                        (None, None)
+                    },
+                    Action::Recover => {
+                        let autosave = autosave_path(&ship_path, &res);
+                        match open(&autosave, &block_registry) {
+                            Ok(recovered) => {
+                                println!("Recovered {}", autosave);
+                                (ship_path.clone(), Some(Ok(recovered)))
+                            },
+                            Err(err) => {
+                                eprintln!("{}: {}", autosave, err);
+                                (None, None)
+                            }
+                        }
+                    },
+                    Action::Reload => match ship_path.clone() {
+                        Some(path) => {
+                            println!("Reload");
+                            let loaded = open(&path, &block_registry);
+                            (Some(path), Some(loaded))
+                        },
+                        None => (None, None)
+                    },
+                    Action::New => (None, Some(Ok(Ship::new("New Ship".to_string())))),
+                    Action::Open(path) => {
+                        let loaded = open(&path, &block_registry);
+                        (Some(path), Some(loaded))
+                    }
+                };
+                match loaded {
+                    Some(Ok(new_ship)) => {
+                        // A recording only covers the ship it started with
+                        stop_recording(&engine, &mut snapshot, &mut record_path);
+                        // The recovered edits are not in the ship file yet
+                        if call(&engine, &mut snapshot, Command::Load { ship: new_ship, saved: ! recovering }) {
+                            // Totals of the old ship would be mixed up with the new one
+                            telemetry.clear();
+                            telemetry.watches.clear();
+                            if ! recovering {
+                                let _ = fs::remove_file(autosave_path(&ship_path, &res));
+                            }
+                            ship_path = path;
+                            drag = None;
+                            selection.clear();
+                            field = None;
+                            editing = None;
+                            fit(&mut camera, &snapshot.ship, if show_palette { PALETTE_W } else { 0 }, window.width(), window.height());
+                        }
+                    },
+                    Some(Err(err)) => eprintln!("{}: {}", path.unwrap_or_default(), err),
+                    None => ()
+                }
+                redraw = true;
             }
         }
 
         thread::sleep(Duration::from_millis(10));
     }
-
-    handle.join().unwrap();
 }
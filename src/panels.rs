@@ -0,0 +1,268 @@
+use orbclient::{Color, Window};
+use orbfont::Font;
+use orbimage::Image;
+
+use starship::block::Block;
+use starship::blueprint::{Blueprint, BlueprintLibrary};
+use starship::kind::{BlockKind, BlockRegistry, BlockVariant};
+use starship::telemetry::{Source, Telemetry};
+
+use frontier::render;
+
+use std::collections::BTreeMap;
+
+/// Top of the panels, below the header
+pub const PANEL_Y: i32 = 28;
+
+/// Width of the palette on the left of the window
+pub const PALETTE_W: i32 = 200;
+/// Height of each palette entry, which shows its image at this size
+pub const PALETTE_ROW: i32 = 26;
+
+/// Width of the inspector on the right of the window
+pub const INSPECTOR_W: i32 = 320;
+/// Height of each row in the inspector
+const INSPECTOR_ROW: i32 = 20;
+/// Left edge of the amount and capacity fields, relative to the inspector
+const AMOUNT_X: i32 = 130;
+const CAPACITY_X: i32 = 224;
+const FIELD_W: i32 = 88;
+
+/// Height of each graph of recorded totals
+pub const GRAPH_H: i32 = 120;
+
+/// A kind, or one variant of a kind, that can be placed from the palette
+pub struct PaletteEntry {
+    pub kind: String,
+    /// Index into `BlockKind::variants`
+    pub variant: Option<usize>,
+    pub label: String
+}
+
+impl PaletteEntry {
+    pub fn get<'r>(&self, registry: &'r BlockRegistry) -> Option<(&'r BlockKind, Option<&'r BlockVariant>)> {
+        registry.get(&self.kind).map(|kind| (kind, self.variant.and_then(|i| kind.variants.get(i))))
+    }
+}
+
+/// Every kind in the registry, with its variants. A kind with variants but no hotkey of its own is only listed by variant.
+pub fn palette_entries(registry: &BlockRegistry) -> Vec<PaletteEntry> {
+    let mut entries = vec![];
+    for kind in registry.kinds.values() {
+        if kind.variants.is_empty() || kind.hotkey.is_some() {
+            entries.push(PaletteEntry {
+                kind: kind.name.clone(),
+                variant: None,
+                label: match kind.hotkey {
+                    Some(hotkey) => format!("{} ({})", kind.name, hotkey),
+                    None => kind.name.clone()
+                }
+            });
+        }
+
+        for (i, variant) in kind.variants.iter().enumerate() {
+            let resources: Vec<&str> = variant.resources.keys().map(|name| name.as_str()).collect();
+            entries.push(PaletteEntry {
+                kind: kind.name.clone(),
+                variant: Some(i),
+                label: format!("{} ({}) {}", kind.name, variant.hotkey, resources.join(", "))
+            });
+        }
+    }
+    entries
+}
+
+/// Index of the palette entry, or blueprint, at a window position
+pub fn palette_entry_at(x: i32, y: i32, entries: usize) -> Option<usize> {
+    if x >= 0 && x < PALETTE_W && y >= PANEL_Y + 20 {
+        let i = ((y - PANEL_Y - 20)/PALETTE_ROW) as usize;
+        if i < entries {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Split text into lines of at most `width` characters, at spaces where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if ! line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(line);
+            line = String::new();
+        }
+        if ! line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if ! line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draw the palette, with the brush marked and the description of the hovered entry below the list
+pub fn draw_palette(window: &mut Window, font: &Font, entries: &[PaletteEntry], images: &BTreeMap<String, Image>, registry: &BlockRegistry, hover: Option<usize>, brush: usize) {
+    let panel_h = window.height() as i32 - PANEL_Y;
+    window.rect(0, PANEL_Y, PALETTE_W as u32, panel_h as u32, Color::rgb(255, 255, 255));
+    window.rect(PALETTE_W - 2, PANEL_Y, 2, panel_h as u32, Color::rgb(0, 0, 0));
+    font.render("Blocks", 16.0).draw(window, 6, PANEL_Y + 2, Color::rgb(0, 0, 0));
+
+    for (i, entry) in entries.iter().enumerate() {
+        let y = PANEL_Y + 20 + i as i32 * PALETTE_ROW;
+        if hover == Some(i) {
+            window.rect(0, y, (PALETTE_W - 2) as u32, PALETTE_ROW as u32, Color::rgb(224, 224, 255));
+        }
+        if brush == i {
+            window.rect(0, y, 2, PALETTE_ROW as u32, Color::rgb(0, 192, 0));
+        }
+        if let Some(image) = images.get(&entry.kind) {
+            image.draw(window, 4, y + 1);
+        }
+        font.render(&entry.label, 12.0).draw(window, 8 + PALETTE_ROW, y + 6, Color::rgb(0, 0, 0));
+    }
+
+    if let Some(kind) = hover.and_then(|i| entries.get(i)).and_then(|entry| registry.get(&entry.kind)) {
+        let y = PANEL_Y + 28 + entries.len() as i32 * PALETTE_ROW;
+        for (i, line) in wrap(&kind.description, 26).iter().enumerate() {
+            font.render(line, 12.0).draw(window, 6, y + i as i32 * 14, Color::rgb(64, 64, 64));
+        }
+    }
+}
+
+/// Draw the blueprint browser in place of the palette, with the chosen blueprint marked and the description of the hovered one below the list
+pub fn draw_blueprints(window: &mut Window, font: &Font, library: &BlueprintLibrary, hover: Option<usize>, chosen: Option<usize>) {
+    let panel_h = window.height() as i32 - PANEL_Y;
+    window.rect(0, PANEL_Y, PALETTE_W as u32, panel_h as u32, Color::rgb(255, 255, 255));
+    window.rect(PALETTE_W - 2, PANEL_Y, 2, panel_h as u32, Color::rgb(0, 0, 0));
+    font.render("Blueprints", 16.0).draw(window, 6, PANEL_Y + 2, Color::rgb(0, 0, 0));
+
+    for (i, blueprint) in library.blueprints.values().enumerate() {
+        let y = PANEL_Y + 20 + i as i32 * PALETTE_ROW;
+        if hover == Some(i) {
+            window.rect(0, y, (PALETTE_W - 2) as u32, PALETTE_ROW as u32, Color::rgb(224, 224, 255));
+        }
+        if chosen == Some(i) {
+            window.rect(0, y, 2, PALETTE_ROW as u32, Color::rgb(0, 192, 0));
+        }
+        let (w, h) = blueprint.size();
+        font.render(&format!("{} ({}x{})", blueprint.name, w, h), 12.0).draw(window, 6, y + 6, Color::rgb(0, 0, 0));
+    }
+
+    let hovered: Option<&Blueprint> = hover.and_then(|i| library.blueprints.values().nth(i));
+    if let Some(blueprint) = hovered {
+        let y = PANEL_Y + 28 + library.blueprints.len() as i32 * PALETTE_ROW;
+        for (i, line) in wrap(&blueprint.description, 26).iter().enumerate() {
+            font.render(line, 12.0).draw(window, 6, y + i as i32 * 14, Color::rgb(64, 64, 64));
+        }
+    }
+}
+
+/// An amount or capacity being typed into the inspector
+pub struct Field {
+    /// Index of the block in `Deck::blocks`
+    pub block: usize,
+    pub resource: String,
+    /// True for the capacity, false for the amount
+    pub capacity: bool,
+    pub text: String
+}
+
+/// The resource and field, true for capacity, at a window position in the inspector
+pub fn inspector_field_at(x: i32, y: i32, window_w: u32, block: &Block) -> Option<(String, bool)> {
+    let panel_x = window_w as i32 - INSPECTOR_W;
+    for (i, name) in block.resources.keys().enumerate() {
+        let row_y = PANEL_Y + 48 + i as i32 * INSPECTOR_ROW;
+        if y >= row_y && y < row_y + INSPECTOR_ROW {
+            if x >= panel_x + AMOUNT_X && x < panel_x + AMOUNT_X + FIELD_W {
+                return Some((name.to_string(), false));
+            }
+            if x >= panel_x + CAPACITY_X && x < panel_x + CAPACITY_X + FIELD_W {
+                return Some((name.to_string(), true));
+            }
+        }
+    }
+    None
+}
+
+/// Draw the kind, position and resources of a block, with the field being edited, if any
+pub fn draw_inspector(window: &mut Window, font: &Font, block: &Block, field: Option<&Field>) {
+    let panel_x = window.width() as i32 - INSPECTOR_W;
+    let panel_h = window.height() as i32 - PANEL_Y;
+    window.rect(panel_x, PANEL_Y, INSPECTOR_W as u32, panel_h as u32, Color::rgb(255, 255, 255));
+    window.rect(panel_x, PANEL_Y, 2, panel_h as u32, Color::rgb(0, 0, 0));
+
+    font.render(&block.kind, 16.0).draw(window, panel_x + 6, PANEL_Y + 2, Color::rgb(0, 0, 0));
+    font.render(&format!("Position {}, {}", block.x, block.y), 12.0).draw(window, panel_x + 6, PANEL_Y + 22, Color::rgb(64, 64, 64));
+    font.render("amount", 12.0).draw(window, panel_x + AMOUNT_X, PANEL_Y + 34, Color::rgb(64, 64, 64));
+    font.render("capacity", 12.0).draw(window, panel_x + CAPACITY_X, PANEL_Y + 34, Color::rgb(64, 64, 64));
+
+    for (i, (name, resource)) in block.resources.iter().enumerate() {
+        let y = PANEL_Y + 48 + i as i32 * INSPECTOR_ROW;
+        font.render(name, 16.0).draw(window, panel_x + 6, y, Color::rgb(0, 0, 0));
+
+        for &(capacity, x) in [(false, AMOUNT_X), (true, CAPACITY_X)].iter() {
+            let editing = field.is_some_and(|field| field.resource == *name && field.capacity == capacity);
+            let text = match field {
+                Some(field) if editing => format!("{}_", field.text),
+                _ => format!("{:.2}", if capacity { resource.capacity } else { resource.amount })
+            };
+            let background = if editing { Color::rgb(255, 255, 192) } else { Color::rgb(240, 240, 240) };
+            window.rect(panel_x + x, y, FIELD_W as u32, (INSPECTOR_ROW - 2) as u32, background);
+            font.render(&text, 16.0).draw(window, panel_x + x + 2, y, Color::rgb(0, 0, 0));
+        }
+    }
+
+    let mut y = PANEL_Y + 52 + block.resources.len() as i32 * INSPECTOR_ROW;
+    if let Some(ref sensor) = block.sensor {
+        font.render(&format!("Sensor: {} below {:.0}%", sensor.resource, sensor.threshold * 100.0), 12.0).draw(window, panel_x + 6, y, Color::rgb(64, 64, 64));
+        y += 16;
+    }
+    if let Some(open) = block.open {
+        font.render(if open { "Door: open" } else { "Door: closed" }, 12.0).draw(window, panel_x + 6, y, Color::rgb(64, 64, 64));
+    }
+}
+
+/// Draw a graph of the recorded totals of each source, one above the other with the last ending at `bottom`.
+/// Each resource is scaled to its own largest total, which is given in the key.
+pub fn draw_graphs(window: &mut Window, font: &Font, telemetry: &Telemetry, sources: &[(String, Source)], x: i32, bottom: i32, w: i32) {
+    let (start, end) = match (telemetry.samples().front(), telemetry.samples().back()) {
+        (Some(first), Some(last)) => (first.time, last.time),
+        _ => (0.0, 0.0)
+    };
+    let span = if end > start { end - start } else { 1.0 };
+
+    for (i, &(ref name, source)) in sources.iter().enumerate() {
+        let y = bottom - (sources.len() - i) as i32 * GRAPH_H;
+        window.rect(x, y, w as u32, GRAPH_H as u32, Color::rgb(255, 255, 255));
+        window.rect(x, y, w as u32, 1, Color::rgb(0, 0, 0));
+        font.render(&format!("{} ({:.1}s)", name, end - start), 12.0).draw(window, x + 4, y + 2, Color::rgb(0, 0, 0));
+
+        // The plot is below the title and to the left of the key
+        let (plot_x, plot_y, plot_w, plot_h) = (x + 4, y + 18, w - 140, GRAPH_H - 22);
+        window.rect(plot_x, plot_y + plot_h, plot_w as u32, 1, Color::rgb(192, 192, 192));
+
+        for (j, resource) in telemetry.resources(source).iter().enumerate() {
+            let series = telemetry.series(source, resource);
+            let most = series.iter().fold(0.0, |most: f64, &(_, total)| most.max(total));
+            let color = render::resource_color(resource);
+            let point = |&(time, total): &(f64, f64)| {
+                let px = plot_x + ((time - start)/span * plot_w as f64) as i32;
+                let py = plot_y + plot_h - if most > 0.0 { (total/most * plot_h as f64) as i32 } else { 0 };
+                (px, py)
+            };
+
+            for pair in series.windows(2) {
+                let (x1, y1) = point(&pair[0]);
+                let (x2, y2) = point(&pair[1]);
+                window.line(x1, y1, x2, y2, color);
+            }
+
+            let key_y = plot_y + j as i32 * 14;
+            window.rect(plot_x + plot_w + 8, key_y + 3, 8, 8, color);
+            font.render(&format!("{} {:.1}", resource, most), 12.0).draw(window, plot_x + plot_w + 20, key_y, Color::rgb(0, 0, 0));
+        }
+    }
+}
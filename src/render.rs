@@ -0,0 +1,457 @@
+use orbclient::Color;
+use orbimage::Image;
+use png;
+
+use starship::block::BlockResource;
+use starship::deck::Deck;
+use starship::flow::FlowReport;
+use starship::kind::BlockRegistry;
+
+use camera::Camera;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind};
+use std::path::Path;
+
+/// Size of block images, and of tiles when rendering at full size
+pub const TILE_SIZE: i32 = 32;
+
+/// A length of `n` pixels on a `TILE_SIZE` tile, scaled to tiles of `size` pixels and at least 1 pixel
+pub fn tile_px(n: i32, size: i32) -> i32 {
+    (n * size/TILE_SIZE).max(1)
+}
+
+/// Resources with a pressure overlay, in the order the overlays are cycled through
+pub const PRESSURE_RESOURCES: [&'static str; 5] = ["air", "electricity", "fuel", "water", "free_air"];
+
+/// What is drawn over the blocks of a deck
+#[derive(Clone, Debug, PartialEq)]
+pub enum Overlay {
+    None,
+    /// A bar for each resource of each block, and the outline of each room
+    Bars,
+    /// Each tile tinted by the pressure of one resource, and the outline of each room
+    Pressure(String),
+    /// Arrows for the net transfers of the last update
+    Flow
+}
+
+impl Overlay {
+    /// Parse the name used by `name`
+    pub fn from_name(name: &str) -> Option<Overlay> {
+        match name {
+            "none" => Some(Overlay::None),
+            "bars" => Some(Overlay::Bars),
+            "flow" => Some(Overlay::Flow),
+            _ => PRESSURE_RESOURCES.iter().find(|&&resource| resource == name).map(|resource| Overlay::Pressure(resource.to_string()))
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            Overlay::None => "none",
+            Overlay::Bars => "bars",
+            Overlay::Pressure(ref resource) => resource,
+            Overlay::Flow => "flow"
+        }
+    }
+
+    /// The overlay after this one: bars, then the pressure of each resource, then flow, then none
+    pub fn next(&self) -> Overlay {
+        match *self {
+            Overlay::None => Overlay::Bars,
+            Overlay::Bars => Overlay::Pressure(PRESSURE_RESOURCES[0].to_string()),
+            Overlay::Pressure(ref resource) => match PRESSURE_RESOURCES.iter().position(|&r| r == resource) {
+                Some(i) if i + 1 < PRESSURE_RESOURCES.len() => Overlay::Pressure(PRESSURE_RESOURCES[i + 1].to_string()),
+                _ => Overlay::Flow
+            },
+            Overlay::Flow => Overlay::None
+        }
+    }
+}
+
+/// Color of the bar showing a resource
+pub fn resource_color(name: &str) -> Color {
+    match name {
+        "air" => Color::rgb(0, 255, 0),
+        "electricity" => Color::rgb(255, 255, 0),
+        "free_air" => Color::rgb(0, 255, 255),
+        "fuel" => Color::rgb(255, 0, 0),
+        "signal" => Color::rgb(255, 128, 0),
+        "water" => Color::rgb(0, 0, 255),
+        _ => Color::rgb(255, 0, 255)
+    }
+}
+
+/// Tint for a pressure from 0 to 1, from red when empty through yellow to green when full
+pub fn pressure_color(pressure: f64) -> Color {
+    let pressure = pressure.max(0.0).min(1.0);
+    if pressure < 0.5 {
+        Color::rgba(255, (pressure * 2.0 * 255.0) as u8, 0, 160)
+    } else {
+        Color::rgba(((1.0 - pressure) * 2.0 * 255.0) as u8, 255, 0, 160)
+    }
+}
+
+/// How full the bar of a resource is, from 0 to 1. Amounts outside the capacity, and capacities of 0 or less, are clamped.
+pub fn bar_fill(resource: &BlockResource) -> f64 {
+    if resource.capacity > 0.0 {
+        (resource.amount/resource.capacity).max(0.0).min(1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Color of the outline of a room
+pub fn room_color(breached: bool) -> Color {
+    if breached { Color::rgb(255, 0, 0) } else { Color::rgb(0, 128, 255) }
+}
+
+/// Color of the bar under a door
+pub fn door_color(open: bool) -> Color {
+    if open { Color::rgb(0, 192, 0) } else { Color::rgb(192, 0, 0) }
+}
+
+/// Height in pixels of the legend row, which is added under exported decks
+pub const LEGEND_H: i32 = 20;
+
+/// Width in pixels of the scale of pressure colors in a legend
+pub const LEGEND_SCALE_W: i32 = 100;
+
+/// Letters of the legend, 3 by 5 pixels, with 3 bits for each row from the top and the high bit on the left.
+/// Exports have no window to draw a font on, so the legend draws its own text.
+const GLYPHS: [(char, u16); 38] = [
+    ('a', 0b010_101_111_101_101), ('b', 0b110_101_110_101_110), ('c', 0b011_100_100_100_011), ('d', 0b110_101_101_101_110),
+    ('e', 0b111_100_110_100_111), ('f', 0b111_100_110_100_100), ('g', 0b011_100_101_101_011), ('h', 0b101_101_111_101_101),
+    ('i', 0b111_010_010_010_111), ('j', 0b001_001_001_101_010), ('k', 0b101_101_110_101_101), ('l', 0b100_100_100_100_111),
+    ('m', 0b101_111_111_101_101), ('n', 0b110_101_101_101_101), ('o', 0b010_101_101_101_010), ('p', 0b110_101_110_100_100),
+    ('q', 0b010_101_101_110_011), ('r', 0b110_101_110_101_101), ('s', 0b011_100_010_001_110), ('t', 0b111_010_010_010_010),
+    ('u', 0b101_101_101_101_111), ('v', 0b101_101_101_101_010), ('w', 0b101_101_111_111_101), ('x', 0b101_101_010_101_101),
+    ('y', 0b101_101_010_010_010), ('z', 0b111_001_010_100_111), ('0', 0b111_101_101_101_111), ('1', 0b010_110_010_010_111),
+    ('2', 0b110_001_010_100_111), ('3', 0b110_001_010_001_110), ('4', 0b101_101_111_001_001), ('5', 0b111_100_110_001_110),
+    ('6', 0b011_100_110_101_010), ('7', 0b111_001_010_010_010), ('8', 0b010_101_010_101_010), ('9', 0b010_101_011_001_110),
+    ('%', 0b101_001_010_100_101), ('_', 0b000_000_000_000_111)
+];
+
+/// Pixels in the legend for each pixel of a letter
+const GLYPH_SCALE: i32 = 2;
+
+/// Width in pixels of text in the legend
+pub fn text_width(text: &str) -> i32 {
+    (text.chars().count() as i32 * 4 - 1).max(0) * GLYPH_SCALE
+}
+
+/// Draw text in the legend's letters with its top left corner at (x, y). Unknown characters are drawn as boxes.
+pub fn text(image: &mut Image, text: &str, x: i32, y: i32, color: Color) {
+    for (i, c) in text.chars().enumerate() {
+        if c == ' ' {
+            continue;
+        }
+        let c = c.to_ascii_lowercase();
+        let bits = GLYPHS.iter().find(|glyph| glyph.0 == c).map_or(0b111_101_101_101_111, |glyph| glyph.1);
+        let left = x + i as i32 * 4 * GLYPH_SCALE;
+        for row in 0 .. 5 {
+            for column in 0 .. 3 {
+                if bits >> (14 - row * 3 - column) & 1 == 1 {
+                    rect(image, left + column * GLYPH_SCALE, y + row * GLYPH_SCALE, GLYPH_SCALE as u32, GLYPH_SCALE as u32, color);
+                }
+            }
+        }
+    }
+}
+
+/// A part of a legend, at a distance in pixels from its left edge
+#[derive(Clone, Debug, PartialEq)]
+pub enum LegendItem {
+    /// A square in the color of a resource
+    Swatch(i32, Color),
+    /// The pressure colors from empty to full
+    Scale(i32),
+    Label(i32, String)
+}
+
+/// The parts of the legend explaining the colors of an overlay, from left to right, and the width of the legend
+pub fn legend_items(overlay: &Overlay) -> (Vec<LegendItem>, i32) {
+    let mut items = vec![];
+    let mut x = 0;
+    match *overlay {
+        Overlay::None => return (items, 0),
+        Overlay::Pressure(ref resource) => {
+            let label = format!("{} 0%", resource);
+            let width = text_width(&label);
+            items.push(LegendItem::Label(x + 4, label));
+            x += width + 8;
+            items.push(LegendItem::Scale(x));
+            x += LEGEND_SCALE_W + 4;
+            items.push(LegendItem::Label(x, "100%".to_string()));
+            x += text_width("100%") + 4;
+        },
+        Overlay::Bars | Overlay::Flow => for resource in PRESSURE_RESOURCES.iter() {
+            items.push(LegendItem::Swatch(x + 4, resource_color(resource)));
+            items.push(LegendItem::Label(x + 20, resource.to_string()));
+            x += text_width(resource) + 24;
+        }
+    }
+    (items, x)
+}
+
+/// The legend of an overlay on white, `LEGEND_H` pixels high, or nothing if the overlay has no colors to explain
+pub fn legend(overlay: &Overlay) -> Option<Image> {
+    let (items, width) = legend_items(overlay);
+    if items.is_empty() {
+        return None;
+    }
+
+    let mut image = Image::from_color(width as u32, LEGEND_H as u32, Color::rgb(255, 255, 255));
+    for item in items.iter() {
+        match *item {
+            LegendItem::Swatch(x, color) => rect(&mut image, x, 4, 12, 12, color),
+            LegendItem::Scale(x) => for i in 0 .. LEGEND_SCALE_W {
+                let color = pressure_color(i as f64/(LEGEND_SCALE_W - 1) as f64);
+                rect(&mut image, x + i, 3, 1, 14, Color { data: color.data | 0xFF000000 });
+            },
+            LegendItem::Label(x, ref label) => text(&mut image, label, x, 5, Color::rgb(0, 0, 0))
+        }
+    }
+    Some(image)
+}
+
+/// Load `<Kind>/image.png` for every kind in a directory, such as `res/blocks/`
+pub fn load_images<P: AsRef<Path>>(path: P) -> io::Result<BTreeMap<String, Image>> {
+    let mut images = BTreeMap::new();
+    for entry_result in fs::read_dir(path)? {
+        let entry = entry_result?;
+        let mut image_path = entry.path();
+        image_path.push("image.png");
+        if image_path.is_file() {
+            let name = entry.file_name().into_string().map_err(|_| Error::new(ErrorKind::InvalidData, "block kind name is not valid UTF-8"))?;
+            let image = Image::from_path(&image_path).map_err(|err| Error::new(ErrorKind::InvalidData, format!("{}: {}", image_path.display(), err)))?;
+            images.insert(name, image);
+        }
+    }
+    Ok(images)
+}
+
+/// Resize an image to a square of `size` pixels, by nearest neighbor
+pub fn scale(image: &Image, size: u32) -> Image {
+    let (w, h) = (image.width(), image.height());
+    let data = image.data();
+    let mut scaled = Vec::with_capacity((size * size) as usize);
+    for y in 0 .. size {
+        for x in 0 .. size {
+            scaled.push(data.get((y * h/size * w + x * w/size) as usize).cloned().unwrap_or(Color::rgba(0, 0, 0, 0)));
+        }
+    }
+    Image::from_data(size, size, scaled.into_boxed_slice()).unwrap()
+}
+
+/// Mix `color` over `under` by the alpha of `color`. The result is opaque if `under` is.
+fn blend(under: Color, color: Color) -> Color {
+    let alpha = color.data >> 24;
+    match alpha {
+        0 => under,
+        255 => color,
+        _ => {
+            let mix = |shift: u32| (((color.data >> shift) & 0xFF) * alpha + ((under.data >> shift) & 0xFF) * (255 - alpha))/255;
+            let under_alpha = under.data >> 24;
+            Color { data: (alpha + under_alpha * (255 - alpha)/255) << 24 | mix(16) << 16 | mix(8) << 8 | mix(0) }
+        }
+    }
+}
+
+/// Fill a rectangle, clipped to the image
+pub fn rect(image: &mut Image, x: i32, y: i32, w: u32, h: u32, color: Color) {
+    let (image_w, image_h) = (image.width() as i32, image.height() as i32);
+    let (start_x, end_x) = (x.max(0), (x + w as i32).min(image_w));
+    let (start_y, end_y) = (y.max(0), (y + h as i32).min(image_h));
+    let data = image.data_mut();
+    for row in start_y .. end_y {
+        for column in start_x .. end_x {
+            let i = (row * image_w + column) as usize;
+            data[i] = blend(data[i], color);
+        }
+    }
+}
+
+/// Draw a line one pixel wide, clipped to the image
+pub fn line(image: &mut Image, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    loop {
+        rect(image, x, y, 1, 1, color);
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+/// The start, end and the two ends of the head of the arrow for a transfer between the centers of two tiles.
+/// Arrows are shifted sideways by `offset` pixels, so that arrows of different resources between the same tiles do not overlap.
+pub fn arrow(from: (i32, i32), to: (i32, i32), size: i32, offset: i32) -> ((i32, i32), (i32, i32), (i32, i32), (i32, i32)) {
+    let (dx, dy) = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+    let length = (dx * dx + dy * dy).sqrt().max(1.0);
+    let (ux, uy) = (dx/length, dy/length);
+    let (nx, ny) = (-uy * offset as f64, ux * offset as f64);
+    let half = size as f64 * 0.4;
+    let head = size as f64 * 0.2;
+
+    let (cx, cy) = ((from.0 + to.0) as f64/2.0 + nx, (from.1 + to.1) as f64/2.0 + ny);
+    let start = (cx - ux * half, cy - uy * half);
+    let end = (cx + ux * half, cy + uy * half);
+    let left = (end.0 - (ux - uy) * head, end.1 - (uy + ux) * head);
+    let right = (end.0 - (ux + uy) * head, end.1 - (uy - ux) * head);
+    let round = |(x, y): (f64, f64)| (x.round() as i32, y.round() as i32);
+    (round(start), round(end), round(left), round(right))
+}
+
+/// Draw an image with its top left corner at (x, y), clipped to the target
+pub fn draw_image(target: &mut Image, image: &Image, x: i32, y: i32) {
+    let (target_w, target_h) = (target.width() as i32, target.height() as i32);
+    let image_w = image.width() as i32;
+    let source = image.data();
+    let data = target.data_mut();
+    for row in 0 .. image.height() as i32 {
+        for column in 0 .. image_w {
+            let (target_x, target_y) = (x + column, y + row);
+            if target_x >= 0 && target_y >= 0 && target_x < target_w && target_y < target_h {
+                let i = (target_y * target_w + target_x) as usize;
+                data[i] = blend(data[i], source[(row * image_w + column) as usize]);
+            }
+        }
+    }
+}
+
+/// Draw the blocks of a deck as seen through a camera, with images already scaled to the camera's tile size, and an overlay.
+/// The flow overlay shows `report`, which should be the last report of this deck, and rooms are found with the kinds in `registry`.
+pub fn draw_deck(target: &mut Image, deck: &Deck, registry: &BlockRegistry, camera: &Camera, images: &BTreeMap<String, Image>, overlay: &Overlay, report: Option<&FlowReport>) {
+    // Sizes are given for full size tiles, and scaled to the zoom level
+    let size = camera.size();
+    let px = |n: i32| tile_px(n, size);
+    let (target_w, target_h) = (target.width() as i32, target.height() as i32);
+
+    for block in deck.blocks.iter() {
+        let (x, y) = camera.to_window(block.x, block.y);
+        if x + size <= 0 || y + size <= 0 || x >= target_w || y >= target_h {
+            continue;
+        }
+
+        match images.get(&block.kind) {
+            Some(image) => draw_image(target, image, x, y),
+            None => rect(target, x, y, size as u32, size as u32, Color::rgb(128, 128, 128))
+        }
+
+        // Doors show a green bar when open, and a red bar when closed
+        if let Some(open) = block.open {
+            rect(target, x + px(2), y + px(28), px(28) as u32, px(2) as u32, door_color(open));
+        }
+
+        match *overlay {
+            Overlay::Bars => for (i, (name, resource)) in block.resources.iter().enumerate() {
+                let dy = (px(28) as f64 * bar_fill(resource)) as i32;
+                rect(target, x + px(2) + i as i32 * px(6), y + px(30) - dy, px(4) as u32, dy as u32, resource_color(name));
+            },
+            Overlay::Pressure(ref resource) => if let Some(pressure) = block.pressure(resource) {
+                rect(target, x, y, size as u32, size as u32, pressure_color(pressure));
+            },
+            _ => ()
+        }
+    }
+
+    if let (&Overlay::Flow, Some(report)) = (overlay, report) {
+        let transfers = report.net_transfers();
+        let most = transfers.iter().fold(0.0, |most: f64, transfer| most.max(transfer.amount));
+        for transfer in transfers.iter() {
+            let (from, to) = match (deck.blocks.get(transfer.from), deck.blocks.get(transfer.to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => continue
+            };
+            let center = |x: isize, y: isize| {
+                let (window_x, window_y) = camera.to_window(x, y);
+                (window_x + size/2, window_y + size/2)
+            };
+            let index = PRESSURE_RESOURCES.iter().position(|&resource| resource == transfer.resource).unwrap_or(PRESSURE_RESOURCES.len()) as i32;
+            let (start, end, left, right) = arrow(center(from.x, from.y), center(to.x, to.y), size, (index - 2) * px(4));
+
+            // Larger flows are drawn with thicker arrows
+            let color = resource_color(&transfer.resource);
+            let thickness = 1 + (2.0 * transfer.amount/most) as i32;
+            for t in 0 .. thickness {
+                line(target, start.0 + t, start.1 + t, end.0 + t, end.1 + t, color);
+                line(target, end.0 + t, end.1 + t, left.0 + t, left.1 + t, color);
+                line(target, end.0 + t, end.1 + t, right.0 + t, right.1 + t, color);
+            }
+        }
+    }
+
+    if *overlay != Overlay::None {
+        for room in deck.rooms(registry).iter() {
+            let color = room_color(room.breached);
+            for &(room_x, room_y) in room.cells.iter() {
+                let (x, y) = camera.to_window(room_x, room_y);
+                if ! room.contains(room_x - 1, room_y) {
+                    rect(target, x, y, 1, size as u32, color);
+                }
+                if ! room.contains(room_x + 1, room_y) {
+                    rect(target, x + size - 1, y, 1, size as u32, color);
+                }
+                if ! room.contains(room_x, room_y - 1) {
+                    rect(target, x, y, size as u32, 1, color);
+                }
+                if ! room.contains(room_x, room_y + 1) {
+                    rect(target, x, y + size - 1, size as u32, 1, color);
+                }
+            }
+        }
+    }
+}
+
+/// Render a whole deck on white, with a margin of one tile, at `size` pixels per tile, and the legend of the overlay below it.
+/// `images` are block images at any size.
+pub fn render_deck(deck: &Deck, registry: &BlockRegistry, images: &BTreeMap<String, Image>, size: i32, overlay: &Overlay, report: Option<&FlowReport>) -> Image {
+    let (min, max) = deck.bounds().unwrap_or(((0, 0), (0, 0)));
+    let tiles_w = (max.0 - min.0 + 3) as i32;
+    let tiles_h = (max.1 - min.1 + 3) as i32;
+    let legend = legend(overlay);
+    let (legend_w, legend_h) = legend.as_ref().map_or((0, 0), |legend| (legend.width(), legend.height()));
+
+    let camera = Camera::with_size((1 - min.0 as i32) * size, (1 - min.1 as i32) * size, size);
+
+    let scaled: BTreeMap<String, Image> = images.iter().map(|(name, image)| (name.clone(), scale(image, size as u32))).collect();
+    let mut image = Image::from_color(((tiles_w * size) as u32).max(legend_w), (tiles_h * size) as u32 + legend_h, Color::rgb(255, 255, 255));
+    draw_deck(&mut image, deck, registry, &camera, &scaled, overlay, report);
+    if let Some(legend) = legend {
+        draw_image(&mut image, &legend, 0, tiles_h * size);
+    }
+    image
+}
+
+/// Write an image as an 8 bit RGBA PNG
+pub fn save_png<P: AsRef<Path>>(image: &Image, path: P) -> io::Result<()> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut bytes = Vec::with_capacity(image.data().len() * 4);
+    for color in image.data().iter() {
+        bytes.push((color.data >> 16) as u8);
+        bytes.push((color.data >> 8) as u8);
+        bytes.push(color.data as u8);
+        bytes.push((color.data >> 24) as u8);
+    }
+
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(&bytes).map_err(Error::other)
+}
@@ -0,0 +1,165 @@
+use orbclient::Color;
+use orbimage::Image;
+
+use starship::block::Block;
+use starship::deck::Deck;
+use starship::flow::FlowReport;
+use starship::kind::BlockRegistry;
+
+use render::{arrow, bar_fill, door_color, legend_items, pressure_color, resource_color, room_color, tile_px, LegendItem, Overlay, LEGEND_H, LEGEND_SCALE_W, PRESSURE_RESOURCES};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Average color of the opaque pixels of an image, which stands in for the image in SVG
+pub fn average_color(image: &Image) -> Color {
+    let (mut r, mut g, mut b, mut count) = (0, 0, 0, 0);
+    for color in image.data().iter().filter(|color| color.data >> 24 > 0) {
+        r += (color.data >> 16) & 0xFF;
+        g += (color.data >> 8) & 0xFF;
+        b += color.data & 0xFF;
+        count += 1;
+    }
+    if count == 0 {
+        Color::rgb(128, 128, 128)
+    } else {
+        Color::rgb((r/count) as u8, (g/count) as u8, (b/count) as u8)
+    }
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:06x}", color.data & 0xFFFFFF)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Render a whole deck as SVG, with a margin of one tile, at `size` pixels per tile.
+/// Each block is a rectangle in the average color of its image, from `colors`, titled with its kind and position.
+/// Blocks are written in order of position, so decks that differ only in block order give the same SVG,
+/// and changes show up as small diffs. The flow overlay shows `report`, which should be the last report of this deck,
+/// and rooms are found with the kinds in `registry`. The legend of the overlay is written below the deck.
+pub fn deck_svg(deck: &Deck, registry: &BlockRegistry, colors: &BTreeMap<String, Color>, size: i32, overlay: &Overlay, report: Option<&FlowReport>) -> String {
+    let px = |n: i32| tile_px(n, size);
+
+    let (min, max) = deck.bounds().unwrap_or(((0, 0), (0, 0)));
+    let tiles_w = (max.0 - min.0 + 3) as i32;
+    let tiles_h = (max.1 - min.1 + 3) as i32;
+    let to_svg = |x: isize, y: isize| ((x - min.0 + 1) as i32 * size, (y - min.1 + 1) as i32 * size);
+
+    let mut blocks: Vec<&Block> = deck.blocks.iter().collect();
+    blocks.sort_by(|a, b| (a.y, a.x, &a.kind).cmp(&(b.y, b.x, &b.kind)));
+
+    let (legend, legend_w) = legend_items(overlay);
+    let legend_h = if legend.is_empty() { 0 } else { LEGEND_H };
+
+    // Writing to a String cannot fail
+    let mut svg = String::new();
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">", (tiles_w * size).max(legend_w), tiles_h * size + legend_h);
+    let _ = writeln!(svg, "<title>{}</title>", escape(&deck.name));
+    let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>");
+
+    let _ = writeln!(svg, "<g id=\"blocks\">");
+    for block in blocks.iter() {
+        let (x, y) = to_svg(block.x, block.y);
+        let fill = colors.get(&block.kind).cloned().unwrap_or(Color::rgb(128, 128, 128));
+        let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"><title>{} {}, {}</title></rect>",
+                         x, y, size, size, hex(fill), escape(&block.kind), block.x, block.y);
+
+        if let Some(open) = block.open {
+            let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>", x + px(2), y + px(28), px(28), px(2), hex(door_color(open)));
+        }
+
+        match *overlay {
+            Overlay::Bars => for (i, (name, resource)) in block.resources.iter().enumerate() {
+                let dy = (px(28) as f64 * bar_fill(resource)) as i32;
+                if dy > 0 {
+                    let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"><title>{} {:.2}/{:.2}</title></rect>",
+                                     x + px(2) + i as i32 * px(6), y + px(30) - dy, px(4), dy, hex(resource_color(name)), escape(name), resource.amount, resource.capacity);
+                }
+            },
+            Overlay::Pressure(ref resource) => if let Some(pressure) = block.pressure(resource) {
+                let color = pressure_color(pressure);
+                let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" fill-opacity=\"{:.2}\"><title>{} {:.0}%</title></rect>",
+                                 x, y, size, size, hex(color), (color.data >> 24) as f64/255.0, escape(resource), pressure * 100.0);
+            },
+            _ => ()
+        }
+    }
+    let _ = writeln!(svg, "</g>");
+
+    if let (&Overlay::Flow, Some(report)) = (overlay, report) {
+        let transfers = report.net_transfers();
+        let most = transfers.iter().fold(0.0, |most: f64, transfer| most.max(transfer.amount));
+        let center = |x: isize, y: isize| {
+            let (svg_x, svg_y) = to_svg(x, y);
+            (svg_x + size/2, svg_y + size/2)
+        };
+
+        let _ = writeln!(svg, "<g id=\"flow\" fill=\"none\" stroke-linecap=\"round\">");
+        for transfer in transfers.iter() {
+            let (from, to) = match (deck.blocks.get(transfer.from), deck.blocks.get(transfer.to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => continue
+            };
+            let index = PRESSURE_RESOURCES.iter().position(|&resource| resource == transfer.resource).unwrap_or(PRESSURE_RESOURCES.len()) as i32;
+            let (start, end, left, right) = arrow(center(from.x, from.y), center(to.x, to.y), size, (index - 2) * px(4));
+            let _ = writeln!(svg, "<path stroke=\"{}\" stroke-width=\"{}\" d=\"M{} {}L{} {}M{} {}L{} {}L{} {}\"><title>{} {:.4} from {}, {} to {}, {}</title></path>",
+                             hex(resource_color(&transfer.resource)), 1 + (2.0 * transfer.amount/most) as i32,
+                             start.0, start.1, end.0, end.1, left.0, left.1, end.0, end.1, right.0, right.1,
+                             escape(&transfer.resource), transfer.amount, from.x, from.y, to.x, to.y);
+        }
+        let _ = writeln!(svg, "</g>");
+    }
+
+    if *overlay != Overlay::None {
+        let _ = writeln!(svg, "<g id=\"rooms\" fill=\"none\" stroke-width=\"1\">");
+        for room in deck.rooms(registry).iter() {
+            // One path per room, tracing the edges of its cells that do not touch another of its cells
+            let mut path = String::new();
+            for &(room_x, room_y) in room.cells.iter() {
+                let (x, y) = to_svg(room_x, room_y);
+                if ! room.contains(room_x - 1, room_y) {
+                    let _ = write!(path, "M{} {}v{}", x, y, size);
+                }
+                if ! room.contains(room_x + 1, room_y) {
+                    let _ = write!(path, "M{} {}v{}", x + size, y, size);
+                }
+                if ! room.contains(room_x, room_y - 1) {
+                    let _ = write!(path, "M{} {}h{}", x, y, size);
+                }
+                if ! room.contains(room_x, room_y + 1) {
+                    let _ = write!(path, "M{} {}h{}", x, y + size, size);
+                }
+            }
+            let _ = writeln!(svg, "<path stroke=\"{}\" d=\"{}\"><title>{} tiles{}</title></path>",
+                             hex(room_color(room.breached)), path, room.cells.len(), if room.breached { ", breached" } else { "" });
+        }
+        let _ = writeln!(svg, "</g>");
+    }
+
+    if ! legend.is_empty() {
+        let top = tiles_h * size;
+        let _ = writeln!(svg, "<g id=\"legend\" font-family=\"monospace\" font-size=\"12\">");
+        for item in legend.iter() {
+            match *item {
+                LegendItem::Swatch(x, color) => {
+                    let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{}\"/>", x, top + 4, hex(color));
+                },
+                LegendItem::Scale(x) => {
+                    let _ = writeln!(svg, "<linearGradient id=\"scale\"><stop offset=\"0\" stop-color=\"{}\"/><stop offset=\"0.5\" stop-color=\"{}\"/><stop offset=\"1\" stop-color=\"{}\"/></linearGradient>",
+                                     hex(pressure_color(0.0)), hex(pressure_color(0.5)), hex(pressure_color(1.0)));
+                    let _ = writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"14\" fill=\"url(#scale)\"/>", x, top + 3, LEGEND_SCALE_W);
+                },
+                LegendItem::Label(x, ref label) => {
+                    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", x, top + 15, escape(label));
+                }
+            }
+        }
+        let _ = writeln!(svg, "</g>");
+    }
+
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
@@ -0,0 +1,144 @@
+#![allow(clippy::redundant_field_names)]
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate starship;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+
+use starship::cli::{Args, EXIT_LOAD, EXIT_OUTPUT, EXIT_USAGE, EXIT_VALIDATION};
+use starship::kind::BlockRegistry;
+use starship::ship::Ship;
+use starship::simulation::TIMESTEP;
+
+const USAGE: &str = "Usage: frontier-sim [OPTIONS] SHIP
+
+Runs a ship without a display, and prints the total of each resource on each deck.
+
+Options:
+    --ticks N        number of ticks to run (default 1000)
+    --dt SECONDS     simulated seconds per tick (default 0.01)
+    --every N        also record totals every N ticks
+    --blocks DIR     directory of block kinds (default res/blocks/)
+    --format FORMAT  json or csv (default json)
+    --output FILE    write to FILE instead of standard output
+    --help           print this message";
+
+#[derive(Serialize)]
+struct DeckTotals {
+    name: String,
+    totals: BTreeMap<String, f64>
+}
+
+#[derive(Serialize)]
+struct Sample {
+    tick: u64,
+    time: f64,
+    decks: Vec<DeckTotals>
+}
+
+impl Sample {
+    fn new(ship: &Ship, tick: u64, dt: f64) -> Sample {
+        Sample {
+            tick: tick,
+            time: tick as f64 * dt,
+            decks: ship.decks.iter().map(|deck| DeckTotals {
+                name: deck.name.clone(),
+                totals: deck.totals()
+            }).collect()
+        }
+    }
+}
+
+fn write_csv<W: Write>(output: &mut W, samples: &[Sample]) -> io::Result<()> {
+    writeln!(output, "tick,time,deck,resource,total")?;
+    for sample in samples.iter() {
+        for deck in sample.decks.iter() {
+            for (resource, total) in deck.totals.iter() {
+                writeln!(output, "{},{},\"{}\",{},{}", sample.tick, sample.time, deck.name.replace('"', "\"\""), resource, total)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_json<W: Write>(output: &mut W, samples: &[Sample]) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *output, samples)?;
+    writeln!(output)
+}
+
+fn main() {
+    let mut ticks = 1000;
+    let mut dt = TIMESTEP;
+    let mut every = None;
+    let mut blocks = "res/blocks/".to_string();
+    let mut format = "json".to_string();
+    let mut output_path = None;
+    let mut ship_path = None;
+
+    let mut args = Args::new("frontier-sim");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => ticks = args.value("--ticks").parse::<u64>().unwrap_or_else(|_| args.fail(EXIT_USAGE, "--ticks must be a whole number")),
+            "--dt" => dt = args.value("--dt").parse::<f64>().ok().filter(|&dt| dt.is_finite() && dt > 0.0).unwrap_or_else(|| args.fail(EXIT_USAGE, "--dt must be a positive number")),
+            "--every" => every = Some(args.value("--every").parse::<u64>().ok().filter(|&n| n > 0).unwrap_or_else(|| args.fail(EXIT_USAGE, "--every must be a positive whole number"))),
+            "--blocks" => blocks = args.value("--blocks"),
+            "--format" => format = args.value("--format"),
+            "--output" => output_path = Some(args.value("--output")),
+            "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => if arg.starts_with("--") || ship_path.is_some() {
+                args.fail(EXIT_USAGE, &format!("unexpected argument {}\n{}", arg, USAGE));
+            } else {
+                ship_path = Some(arg);
+            }
+        }
+    }
+
+    if format != "json" && format != "csv" {
+        args.fail(EXIT_USAGE, &format!("unknown format {}", format));
+    }
+
+    let ship_path = ship_path.unwrap_or_else(|| args.fail(EXIT_USAGE, USAGE));
+
+    let registry = BlockRegistry::load(&blocks).unwrap_or_else(|err| args.fail(EXIT_LOAD, &format!("{}: {}", blocks, err)));
+    let mut ship = starship::load(&ship_path).unwrap_or_else(|err| args.fail(EXIT_LOAD, &format!("{}: {}", ship_path, err)));
+
+    if let Err(err) = ship.validate(&registry) {
+        args.fail(EXIT_VALIDATION, &format!("{}: {}", ship_path, err));
+    }
+
+    let mut samples = vec![];
+    if every.is_some() && ticks > 0 {
+        samples.push(Sample::new(&ship, 0, dt));
+    }
+    for tick in 1 ..= ticks {
+        ship.update(dt, &registry);
+        if every.is_some_and(|every| tick.is_multiple_of(every)) && tick != ticks {
+            samples.push(Sample::new(&ship, tick, dt));
+        }
+    }
+    samples.push(Sample::new(&ship, ticks, dt));
+
+    let result = {
+        let mut output: Box<dyn Write> = match output_path {
+            Some(ref path) => Box::new(File::create(path).unwrap_or_else(|err| args.fail(EXIT_OUTPUT, &format!("{}: {}", path, err)))),
+            None => Box::new(io::stdout())
+        };
+
+        if format == "csv" {
+            write_csv(&mut output, &samples)
+        } else {
+            write_json(&mut output, &samples)
+        }
+    };
+
+    if let Err(err) = result {
+        args.fail(EXIT_OUTPUT, &format!("{}", err));
+    }
+}
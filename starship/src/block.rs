@@ -1,27 +1,96 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 
-#[derive(Copy, Clone, Debug, Default, RustcDecodable, RustcEncodable)]
+use kind::{BlockKind, BlockRegistry, BlockVariant};
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub struct BlockResource {
     pub amount: f64,
     pub capacity: f64
 }
 
-#[derive(Clone, Debug, RustcDecodable, RustcEncodable)]
+/// Sends a signal on the conduits while the pressure of a resource in its block is below a threshold
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sensor {
+    pub resource: String,
+    /// Pressure, from 0 to 1, below which the sensor is triggered
+    pub threshold: f64
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Block<'a> {
-    pub x: usize,
-    pub y: usize,
+    pub x: isize,
+    pub y: isize,
     pub kind: String,
-    pub resources: BTreeMap<Cow<'a, str>, BlockResource>
+    pub resources: BTreeMap<Cow<'a, str>, BlockResource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<Sensor>,
+    /// Whether a door is open, for blocks that can be opened and closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open: Option<bool>
 }
 
 impl<'a> Block<'a> {
-    pub fn new(x: usize, y: usize, kind: String) -> Block<'a> {
+    pub fn new(x: isize, y: isize, kind: String) -> Block<'a> {
         Block {
             x: x,
             y: y,
             kind: kind,
-            resources: BTreeMap::new()
+            resources: BTreeMap::new(),
+            sensor: None,
+            open: None
+        }
+    }
+
+    /// Create a block with the default resources of the kind named `kind`, or None if the registry has no such kind
+    pub fn from_kind(x: isize, y: isize, kind: &str, registry: &BlockRegistry) -> Option<Block<'a>> {
+        registry.get(kind).map(|kind| Block::with_resources(x, y, kind, &kind.resources))
+    }
+
+    /// Create a block of a kind, with the resources of one of its variants
+    pub fn from_variant(x: isize, y: isize, kind: &BlockKind, variant: &BlockVariant) -> Block<'a> {
+        Block::with_resources(x, y, kind, &variant.resources)
+    }
+
+    fn with_resources(x: isize, y: isize, kind: &BlockKind, resources: &BTreeMap<String, BlockResource>) -> Block<'a> {
+        let mut block = Block::new(x, y, kind.name.clone());
+        for (name, resource) in resources.iter() {
+            block.resources.insert(name.clone().into(), *resource);
+        }
+        block.sensor = kind.sensor.clone();
+        block.open = kind.open;
+        block
+    }
+
+    /// The amount of a resource as a fraction of its capacity
+    pub fn pressure(&self, resource: &str) -> Option<f64> {
+        match self.resources.get(resource) {
+            Some(resource) if resource.capacity > 0.0 => Some(resource.amount/resource.capacity),
+            _ => None
+        }
+    }
+
+    /// True if this is a closed door, which stops free_air
+    pub fn closed(&self) -> bool {
+        self.open == Some(false)
+    }
+
+    /// Open a closed door, or close an open one. Returns false if this block is not a door.
+    pub fn toggle(&mut self) -> bool {
+        match self.open {
+            Some(open) => {
+                self.open = Some(! open);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// True if this block has a sensor, and the pressure it watches is below its threshold
+    pub fn sensor_triggered(&self) -> bool {
+        match self.sensor {
+            Some(ref sensor) => self.pressure(&sensor.resource).is_some_and(|pressure| pressure < sensor.threshold),
+            None => false
         }
     }
 }
@@ -0,0 +1,220 @@
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use block::Block;
+use deck::Deck;
+use edit::Edit;
+use error::{Error, Result};
+use selection;
+
+/// A named group of blocks, such as a crew room or a tank farm, that can be stamped onto any deck.
+/// Stored as `res/blueprints/<name>.json`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Blueprint<'a> {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Blocks positioned relative to the top left corner of the blueprint
+    pub blocks: Vec<Block<'a>>
+}
+
+impl<'a> Blueprint<'a> {
+    /// Copy some blocks of a deck into a new blueprint
+    pub fn extract(name: String, deck: &Deck<'a>, indexes: &[usize]) -> Blueprint<'a> {
+        Blueprint {
+            name: name,
+            description: String::new(),
+            blocks: deck.copy_blocks(indexes)
+        }
+    }
+
+    /// Width and height in tiles
+    pub fn size(&self) -> (isize, isize) {
+        match selection::bounds(self.blocks.iter()) {
+            Some((min, max)) => (max.0 - min.0 + 1, max.1 - min.1 + 1),
+            None => (0, 0)
+        }
+    }
+
+    /// Tiles where stamping the blueprint at (x, y) would place a block on top of an existing one
+    pub fn collisions(&self, deck: &Deck, x: isize, y: isize) -> Vec<(isize, isize)> {
+        let mut tiles: Vec<(isize, isize)> = self.blocks.iter()
+            .map(|block| (x + block.x, y + block.y))
+            .filter(|&(tile_x, tile_y)| ! deck.blocks_at(tile_x, tile_y).is_empty())
+            .collect();
+        tiles.sort();
+        tiles.dedup();
+        tiles
+    }
+
+    /// The edit that adds the blueprint to a deck with its top left corner at (x, y).
+    /// Fails without changing anything if any block would land on an occupied tile.
+    pub fn stamp(&self, deck_index: usize, deck: &Deck, x: isize, y: isize) -> Result<Edit<'a>> {
+        let collisions = self.collisions(deck, x, y);
+        if let Some(&(tile_x, tile_y)) = collisions.first() {
+            return Err(Error::InvalidEdit(format!("blueprint {} overlaps {} occupied tiles, first at {}, {}", self.name, collisions.len(), tile_x, tile_y)));
+        }
+
+        let blocks = self.blocks.iter().map(|block| {
+            let mut block = block.clone();
+            block.x += x;
+            block.y += y;
+            block
+        }).collect();
+        Ok(Edit::add_blocks(deck_index, deck.blocks.len(), blocks))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Blueprint<'static>> {
+        let mut file = File::open(path)?;
+
+        let mut string = String::new();
+        file.read_to_string(&mut string)?;
+
+        let blueprint: Blueprint<'static> = serde_json::from_str(&string)?;
+        Ok(blueprint)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = File::create(path)?;
+
+        let string = serde_json::to_string_pretty(self)?;
+        writeln!(file, "{}", string)?;
+        Ok(())
+    }
+}
+
+/// All blueprints in a directory, by name
+#[derive(Clone, Debug, Default)]
+pub struct BlueprintLibrary {
+    pub blueprints: BTreeMap<String, Blueprint<'static>>
+}
+
+impl BlueprintLibrary {
+    pub fn new() -> BlueprintLibrary {
+        BlueprintLibrary::default()
+    }
+
+    /// Load every `*.json` in a directory, such as `res/blueprints/`. A missing directory is an empty library.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BlueprintLibrary> {
+        let mut library = BlueprintLibrary::new();
+        if ! path.as_ref().is_dir() {
+            return Ok(library);
+        }
+
+        for entry_result in fs::read_dir(path)? {
+            let path = entry_result?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let blueprint = Blueprint::load(&path).map_err(|err| Error::Schema(format!("{}: {}", path.display(), err)))?;
+                library.insert(blueprint);
+            }
+        }
+
+        Ok(library)
+    }
+
+    pub fn insert(&mut self, blueprint: Blueprint<'static>) {
+        self.blueprints.insert(blueprint.name.clone(), blueprint);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Blueprint<'static>> {
+        self.blueprints.get(name)
+    }
+
+    /// Save a blueprint as `<name>.json` in a directory, creating the directory if needed, and add it to the library
+    pub fn save<P: AsRef<Path>>(&mut self, path: P, blueprint: Blueprint<'static>) -> Result<()> {
+        fs::create_dir_all(&path)?;
+        blueprint.save(path.as_ref().join(format!("{}.json", file_name(&blueprint.name))))?;
+        self.insert(blueprint);
+        Ok(())
+    }
+}
+
+/// A blueprint name with any characters that are unsafe in file names replaced
+fn file_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use block::Block;
+    use deck::Deck;
+    use error::Error;
+    use ship::Ship;
+
+    use super::{Blueprint, BlueprintLibrary};
+
+    fn positions(blocks: &[Block]) -> Vec<(isize, isize, String)> {
+        blocks.iter().map(|block| (block.x, block.y, block.kind.clone())).collect()
+    }
+
+    fn blueprint<'a>() -> Blueprint<'a> {
+        let deck = Deck::new("Deck 1".to_string(), vec![
+            Block::new(0, 0, "Deck".to_string()),
+            Block::new(1, 0, "Deck".to_string())
+        ]);
+        Blueprint::extract("Pair".to_string(), &deck, &[0, 1])
+    }
+
+    #[test]
+    fn extract_moves_blocks_to_the_top_left() {
+        let deck = Deck::new("Deck 1".to_string(), vec![
+            Block::new(3, 4, "Deck".to_string()),
+            Block::new(9, 9, "Hull".to_string()),
+            Block::new(5, 6, "Vent".to_string())
+        ]);
+
+        let blueprint = Blueprint::extract("Room".to_string(), &deck, &[0, 2]);
+        assert_eq!(positions(&blueprint.blocks), vec![(0, 0, "Deck".to_string()), (2, 2, "Vent".to_string())]);
+        assert_eq!(blueprint.size(), (3, 3));
+    }
+
+    #[test]
+    fn stamp_at_a_negative_offset() {
+        let mut ship = Ship::new("Test".to_string());
+        ship.decks[0] = Deck::new("Deck 1".to_string(), vec![Block::new(0, 0, "Hull".to_string())]);
+
+        let edit = blueprint().stamp(0, &ship.decks[0], -5, -3).unwrap();
+        edit.apply(&mut ship).unwrap();
+        assert_eq!(positions(&ship.decks[0].blocks), vec![
+            (0, 0, "Hull".to_string()),
+            (-5, -3, "Deck".to_string()),
+            (-4, -3, "Deck".to_string())
+        ]);
+    }
+
+    #[test]
+    fn stamp_rejects_collisions() {
+        let deck = Deck::new("Deck 1".to_string(), vec![Block::new(3, 2, "Hull".to_string())]);
+        let blueprint = blueprint();
+
+        assert_eq!(blueprint.collisions(&deck, 2, 2), vec![(3, 2)]);
+        assert!(blueprint.collisions(&deck, 2, 3).is_empty());
+        match blueprint.stamp(0, &deck, 2, 2) {
+            Err(Error::InvalidEdit(_)) => (),
+            other => panic!("expected an invalid edit, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = env::temp_dir().join(format!("starship-blueprints-{}", ::std::process::id()));
+        let mut blueprint = blueprint();
+        blueprint.name = "Pair of decks".to_string();
+        blueprint.description = "Two floor tiles".to_string();
+
+        let mut library = BlueprintLibrary::new();
+        library.save(&dir, blueprint.clone()).unwrap();
+        let loaded = BlueprintLibrary::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.get("Pair of decks").unwrap();
+        assert_eq!(loaded.description, "Two floor tiles");
+        assert_eq!(positions(&loaded.blocks), positions(&blueprint.blocks));
+    }
+}
@@ -0,0 +1,61 @@
+use std::env;
+use std::io::{self, Write};
+use std::iter::Skip;
+use std::process;
+
+use error::Error;
+
+/// Bad command line arguments
+pub const EXIT_USAGE: i32 = 2;
+/// The ship, recording or block kinds could not be loaded
+pub const EXIT_LOAD: i32 = 3;
+/// The ship or recording loaded, but is not valid
+pub const EXIT_VALIDATION: i32 = 4;
+/// The results could not be written
+pub const EXIT_OUTPUT: i32 = 5;
+
+/// The exit status for a ship or recording that could not be opened
+pub fn exit_code(err: &Error) -> i32 {
+    match *err {
+        Error::Validation(_) => EXIT_VALIDATION,
+        _ => EXIT_LOAD
+    }
+}
+
+/// The command line arguments of a tool, after the path of the program
+pub struct Args {
+    /// Name of the tool, printed before error messages
+    pub program: &'static str,
+    args: Skip<env::Args>
+}
+
+impl Args {
+    pub fn new(program: &'static str) -> Args {
+        Args {
+            program: program,
+            args: env::args().skip(1)
+        }
+    }
+
+    /// The value of an option, such as N in `--ticks N`, failing if the arguments end first
+    pub fn value(&mut self, option: &str) -> String {
+        match self.args.next() {
+            Some(value) => value,
+            None => self.fail(EXIT_USAGE, &format!("{} requires a value", option))
+        }
+    }
+
+    /// Print an error to standard error and exit with `code`
+    pub fn fail(&self, code: i32, message: &str) -> ! {
+        let _ = writeln!(io::stderr(), "{}: {}", self.program, message);
+        process::exit(code);
+    }
+}
+
+impl Iterator for Args {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.args.next()
+    }
+}
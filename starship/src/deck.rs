@@ -1,45 +1,194 @@
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::slice;
 
 use block::Block;
+use flow::FlowReport;
+use kind::{BlockRegistry, Process};
+use selection;
 
-#[derive(Clone, Debug, RustcDecodable, RustcEncodable)]
+/// The cells touching a cell, including itself
+const NEIGHBORS: [(isize, isize); 5] = [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Fraction of the pressure difference between two connected nodes equalized per second
+pub const FLOW_RATE: f64 = 100.0;
+
+/// Fraction of free_air lost to space per second, for each vacuum weighted by its distance
+pub const LEAK_RATE: f64 = 1.0;
+
+/// Signal pressure at which a console shows an alert
+pub const ALERT_LEVEL: f64 = 0.05;
+
+/// A triggered sensor
+#[derive(Clone, Debug)]
+pub struct Alert {
+    /// Index of the sensor block in `Deck::blocks`
+    pub block: usize,
+    pub x: isize,
+    pub y: isize,
+    pub resource: String,
+    pub pressure: f64,
+    pub threshold: f64
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(from = "DeckData<'a>")]
 pub struct Deck<'a> {
     pub name: String,
-    pub blocks: Vec<Block<'a>>
+    /// Change blocks with `add_block`, `remove_block` and `move_block` to keep the index current,
+    /// or call `reindex` after modifying them directly
+    pub blocks: Vec<Block<'a>>,
+    /// Indexes into `blocks`, by (x, y)
+    #[serde(skip)]
+    index: HashMap<(isize, isize), Vec<usize>>
 }
 
 #[derive(Debug)]
 struct Node<'a> {
+    d: usize,
     i: usize,
-    x: usize,
-    y: usize,
+    x: isize,
+    y: isize,
     resource: Cow<'a, str>,
     amount: f64,
     capacity: f64,
 }
 
 impl<'a> Node<'a> {
-    fn connected(&self, other: &Node) -> bool {
-        return (self.x == other.x && self.y == other.y)      // same block
-            || (self.x + 1 == other.x && self.y == other.y)  // to the left
-            || (self.x == other.x + 1 && self.y == other.y)  // to the right
-            || (self.x == other.x && self.y + 1 == other.y)  // above
-            || (self.x == other.x && self.y == other.y + 1); // below
-    }
-
     fn pressure(&self) -> f64 {
         return self.amount / self.capacity;
     }
 }
 
+/// A flow of a resource from node i to node j, before limiting
 #[derive(Debug)]
-struct Change {
+struct Flow {
     i: usize,
     j: usize,
-    pressure: f64
+    amount: f64
 }
 
 impl<'a> Deck<'a> {
+    pub fn new(name: String, blocks: Vec<Block<'a>>) -> Deck<'a> {
+        let mut deck = Deck {
+            name: name,
+            blocks: blocks,
+            index: HashMap::new()
+        };
+        deck.reindex();
+        deck
+    }
+
+    /// Rebuild the spatial index from `blocks`
+    pub fn reindex(&mut self) {
+        self.index.clear();
+        for (i, block) in self.blocks.iter().enumerate() {
+            self.index.entry((block.x, block.y)).or_default().push(i);
+        }
+    }
+
+    /// Indexes of all blocks at (x, y), in the order they were added
+    pub fn blocks_at(&self, x: isize, y: isize) -> &[usize] {
+        match self.index.get(&(x, y)) {
+            Some(indexes) => indexes,
+            None => &[]
+        }
+    }
+
+    /// Index of the topmost block at (x, y)
+    pub fn block_at(&self, x: isize, y: isize) -> Option<usize> {
+        self.blocks_at(x, y).last().cloned()
+    }
+
+    pub fn add_block(&mut self, block: Block<'a>) -> usize {
+        let i = self.blocks.len();
+        self.index.entry((block.x, block.y)).or_default().push(i);
+        self.blocks.push(block);
+        i
+    }
+
+    /// Insert a block at an index in `blocks`, shifting the blocks after it
+    pub fn insert_block(&mut self, i: usize, block: Block<'a>) {
+        self.blocks.insert(i, block);
+        self.reindex();
+    }
+
+    pub fn remove_block(&mut self, i: usize) -> Block<'a> {
+        let block = self.blocks.remove(i);
+        self.reindex();
+        block
+    }
+
+    pub fn move_block(&mut self, i: usize, x: isize, y: isize) {
+        let (old_x, old_y) = (self.blocks[i].x, self.blocks[i].y);
+        if old_x == x && old_y == y {
+            return;
+        }
+
+        let empty = match self.index.get_mut(&(old_x, old_y)) {
+            Some(indexes) => {
+                indexes.retain(|&j| j != i);
+                indexes.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.index.remove(&(old_x, old_y));
+        }
+
+        {
+            let indexes = self.index.entry((x, y)).or_default();
+            indexes.push(i);
+            indexes.sort();
+        }
+
+        let block = &mut self.blocks[i];
+        block.x = x;
+        block.y = y;
+    }
+
+    /// The smallest and largest tile positions of any block, or None if the deck is empty
+    pub fn bounds(&self) -> Option<((isize, isize), (isize, isize))> {
+        selection::bounds(self.blocks.iter())
+    }
+
+    /// Total amount of each resource on the deck
+    pub fn totals(&self) -> BTreeMap<String, f64> {
+        let mut totals = BTreeMap::new();
+        for block in self.blocks.iter() {
+            for (name, resource) in block.resources.iter() {
+                *totals.entry(name.to_string()).or_insert(0.0) += resource.amount;
+            }
+        }
+        totals
+    }
+
+    /// All triggered sensors
+    pub fn alerts(&self) -> Vec<Alert> {
+        let mut alerts = vec![];
+        for (i, block) in self.blocks.iter().enumerate() {
+            if !block.sensor_triggered() {
+                continue;
+            }
+            if let Some(ref sensor) = block.sensor {
+                alerts.push(Alert {
+                    block: i,
+                    x: block.x,
+                    y: block.y,
+                    resource: sensor.resource.clone(),
+                    pressure: block.pressure(&sensor.resource).unwrap_or(0.0),
+                    threshold: sensor.threshold
+                });
+            }
+        }
+        alerts
+    }
+
+    /// True if enough signal has reached a block, such as a console, for it to show an alert
+    pub fn signaled(&self, i: usize) -> bool {
+        self.blocks[i].pressure("signal").is_some_and(|pressure| pressure >= ALERT_LEVEL)
+    }
+
     /// # Update the deck
     /// - First, identify resource movement using the following algorithm, repeated until complete:
     ///   - Fill conduits from connected tanks until rate is fulfilled or tanks are drained
@@ -48,130 +197,346 @@ impl<'a> Deck<'a> {
     /// - Next, identify sensor triggers
     ///   - Any sensors that detect low presure will send an alert on the conduits
     ///   - That alert will propogate to nearby computer consoles
-    pub fn update(&mut self) -> bool {
-        let mut redraw = false;
+    ///
+    /// All flows are calculated from the state at the start of the update, then applied together,
+    /// so resources are only created or destroyed by the sources and sinks listed in the returned report.
+    ///
+    /// `dt` is the simulated time in seconds. Rates are per second, so a smaller timestep is more accurate.
+    pub fn update(&mut self, dt: f64, registry: &BlockRegistry) -> FlowReport {
+        Deck::update_all(slice::from_mut(self), dt, registry).remove(0)
+    }
+
+    /// Update a stack of decks together, where `decks[d + 1]` is above `decks[d]`.
+    ///
+    /// Blocks with the "vertical" tag connect to the blocks at the same position on the decks above and below,
+    /// so resources flow through the whole stack as one network. Returns the report of each deck, in order.
+    pub fn update_all(decks: &mut [Deck<'a>], dt: f64, registry: &BlockRegistry) -> Vec<FlowReport> {
+        let mut reports: Vec<FlowReport> = decks.iter().map(|_| FlowReport::new()).collect();
+
+        // Fraction of each pressure difference to equalize, shared among every neighbor
+        let flow_rate = (FLOW_RATE * dt).min(1.0)/NEIGHBORS.len() as f64;
 
         let mut nodes = vec![];
+        // Nodes of block i on deck d are nodes[block_nodes[d][i] .. block_nodes[d][i + 1]]
+        let mut block_nodes = vec![];
+        // Whether block i on deck d connects to the decks above and below
+        let mut vertical = vec![];
+        // Whether block i on deck d is a closed door, sealing its free_air
+        let mut closed = vec![];
 
         // Create nodes from blocks
-        for (i, mut block) in self.blocks.iter_mut().enumerate() {
-            // Vents transfer air to a room
-            if block.kind == "Vent" {
-                if block.resources.contains_key("air") && block.resources.contains_key("free_air") {
-                    let mut air = block.resources["air"].amount;
-                    let mut free_air = block.resources["free_air"].amount;
-                    let capacity = block.resources["free_air"].capacity;
-                    if air > 0.0 && free_air < capacity {
-                        let amount = air.min(capacity - free_air);
-                        air -= amount;
-                        free_air += amount;
-
-                        if let Some(mut resource) = block.resources.get_mut("air") {
-                            resource.amount = air;
-                        }
+        for (d, deck) in decks.iter_mut().enumerate() {
+            let report = &mut reports[d];
+            let mut deck_nodes = vec![nodes.len()];
+            let mut deck_vertical = vec![];
+            let mut deck_closed = vec![];
+
+            for (i, block) in deck.blocks.iter_mut().enumerate() {
+                let kind = registry.get(&block.kind);
 
-                        if let Some(mut resource) = block.resources.get_mut("free_air") {
-                            resource.amount = free_air;
+                // Producers and consumers, such as vents, reactors and crew
+                if let Some(kind) = kind {
+                    for process in kind.processes.iter() {
+                        run_process(process, block, dt, report);
+                    }
+                }
+
+                // Doors close when an alert reaches them
+                if block.open == Some(true) && block.pressure("signal").is_some_and(|pressure| pressure >= ALERT_LEVEL) {
+                    block.open = Some(false);
+                }
+
+                // Sensors that detect low pressure send a signal on the conduits
+                if block.sensor_triggered() {
+                    if let Some(signal) = block.resources.get_mut("signal") {
+                        let amount = signal.capacity - signal.amount;
+                        if amount > 0.0 {
+                            signal.amount += amount;
+                            report.produce("signal", amount);
                         }
                     }
                 }
-            }
 
-            for (name, resource) in block.resources.iter() {
-                nodes.push(Node {
-                    i: i,
-                    x: block.x,
-                    y: block.y,
-                    resource: name.clone(),
-                    amount: resource.amount,
-                    capacity: resource.capacity
-                });
+                deck_vertical.push(kind.is_some_and(|kind| kind.has_tag("vertical")));
+                deck_closed.push(block.closed());
+
+                for (name, resource) in block.resources.iter() {
+                    nodes.push(Node {
+                        d: d,
+                        i: i,
+                        x: block.x,
+                        y: block.y,
+                        resource: name.clone(),
+                        amount: resource.amount,
+                        capacity: resource.capacity
+                    });
+                }
+                deck_nodes.push(nodes.len());
             }
+
+            block_nodes.push(deck_nodes);
+            vertical.push(deck_vertical);
+            closed.push(deck_closed);
         }
 
-        // Create change list
-        let mut changes = vec![];
+        // Calculate flows from the snapshot of every node
+        let mut flows = vec![];
+        let mut outflows = vec![0.0; nodes.len()];
+        let mut inflows = vec![0.0; nodes.len()];
+        // Blocks connected to the current node, as (deck, block)
+        let mut connected = vec![];
+        let sealed = |node: &Node| node.resource == "free_air" && closed[node.d][node.i];
         for i in 0 .. nodes.len() {
-            // Calculate changes for this node
             let a = &nodes[i];
-            let mut node_changes = vec![];
-            //let mut total = 0.0;
-            for j in 0 .. nodes.len() {
-                if j != i {
-                    let b = &nodes[j];
-                    if a.connected(b) && a.resource == b.resource && a.pressure() > b.pressure() {
-                        //println!("{}, {} > {}, {}. {} > {}. {} > {}", a.x, a.y, b.x, b.y, i, j, a.pressure(), b.pressure());
-                        let difference = (a.pressure() - b.pressure())/2.0;
-                        node_changes.push((j, difference));
-                        //total += difference;
-                    }
+            if a.capacity <= 0.0 || sealed(a) {
+                continue;
+            }
+
+            connected.clear();
+            for &(dx, dy) in NEIGHBORS.iter() {
+                for &block_i in decks[a.d].blocks_at(a.x + dx, a.y + dy) {
+                    connected.push((a.d, block_i));
                 }
             }
 
-            //let normalize = a.pressure()/total;
+            // Vertical blocks connect to every block at the same position on the decks above and below
+            for &d in [a.d.wrapping_sub(1), a.d + 1].iter() {
+                if let Some(deck) = decks.get(d) {
+                    for &block_i in deck.blocks_at(a.x, a.y) {
+                        if vertical[a.d][a.i] || vertical[d][block_i] {
+                            connected.push((d, block_i));
+                        }
+                    }
+                }
+            }
 
-            // Normalize changes, so that two competing draws on one resource are shared
-            for (j, pressure) in node_changes {
-                changes.push(Change {
-                    i: i,
-                    j: j,
-                    pressure: pressure
-                });
+            for &(d, block_i) in connected.iter() {
+                for j in block_nodes[d][block_i] .. block_nodes[d][block_i + 1] {
+                    let b = &nodes[j];
+                    if j != i && b.capacity > 0.0 && a.resource == b.resource && ! sealed(b) && a.pressure() > b.pressure() {
+                        // A fraction of the amount that would equalize both pressures
+                        let amount = flow_rate * (a.amount * b.capacity - b.amount * a.capacity)/(a.capacity + b.capacity);
+                        flows.push(Flow {
+                            i: i,
+                            j: j,
+                            amount: amount
+                        });
+                        outflows[i] += amount;
+                        inflows[j] += amount;
+                    }
+                }
             }
         }
 
-        // Apply changes
-        for change in changes {
-            redraw = true;
-
-            let amount = change.pressure.max(0.0).min(1.0) * nodes[change.i].amount.min(nodes[change.j].capacity - nodes[change.j].amount);
-            nodes[change.i].amount -= amount;
-            nodes[change.j].amount += amount;
+        // Scale down flows that would drain a node below empty or fill it beyond capacity
+        let limit = |total: f64, available: f64| if total > available { available.max(0.0)/total } else { 1.0 };
+        let mut changes = vec![0.0; nodes.len()];
+        for flow in flows {
+            let a = &nodes[flow.i];
+            let b = &nodes[flow.j];
+            let amount = flow.amount * limit(outflows[flow.i], a.amount).min(limit(inflows[flow.j], b.capacity - b.amount));
+            if amount > 0.0 {
+                changes[flow.i] -= amount;
+                changes[flow.j] += amount;
+                if a.d == b.d {
+                    reports[a.d].transfer(a.i, b.i, &a.resource, amount);
+                } else {
+                    reports[a.d].export(&a.resource, amount);
+                    reports[b.d].import(&b.resource, amount);
+                }
+            }
         }
 
         // Update blocks from nodes
-        for mut node in nodes {
-            //If free air, check for vacuums
-            if node.resource == "free_air" && node.amount > 0.0 {
-                let ox = node.x as isize - 1;
-                let oy = node.y as isize - 1;
-
-                let mut neighbors: [[usize; 3]; 3] = [
-                    [0, 0, 0],
-                    [0, 0, 0],
-                    [0, 0, 0]
+        for (mut node, change) in nodes.into_iter().zip(changes) {
+            node.amount += change;
+
+            let deck = &mut decks[node.d];
+
+            //If free air, check for vacuums. Closed doors are sealed.
+            if node.resource == "free_air" && node.amount > 0.0 && ! closed[node.d][node.i] {
+                let ox = node.x - 1;
+                let oy = node.y - 1;
+
+                // Tiles that are open to space, such as empty tiles, or ones without a floor or an airtight block
+                let mut neighbors: [[bool; 3]; 3] = [
+                    [false, false, false],
+                    [false, false, false],
+                    [false, false, false]
                 ];
 
-                for block in self.blocks.iter() {
-                    let i = block.x as isize - ox;
-                    let j = block.y as isize - oy;
-                    if i >= 0 && i < 3 && j >= 0 && j < 3 {
-                        neighbors[j as usize][i as usize] += 1;
+                for (j, neighbor_row) in neighbors.iter_mut().enumerate() {
+                    for (i, neighbor) in neighbor_row.iter_mut().enumerate() {
+                        *neighbor = deck.open_to_space(ox + i as isize, oy + j as isize, registry);
                     }
                 }
 
                 let mut vacuums = 0.0;
                 for (y, neighbor_row) in neighbors.iter().enumerate() {
                     for (x, &neighbor) in neighbor_row.iter().enumerate() {
-                        if neighbor == 0 {
+                        if neighbor {
                             vacuums += 1.0/((x as f64 - 1.0).powf(2.0) + (y as f64 - 1.0).powf(2.0)).sqrt();
                         }
                     }
                 }
 
                 if vacuums > 0.0 {
-                    let decay = 1.0 - vacuums/100.0;
-                    //println!("{}, {}: {} vacuums, {} amount, {} decay", node.x, node.y, vacuums, node.amount, decay);
-                    node.amount *= decay;
+                    let vented = node.amount * (vacuums * LEAK_RATE * dt).min(1.0);
+                    //println!("{}, {}: {} vacuums, {} amount, {} vented", node.x, node.y, vacuums, node.amount, vented);
+                    node.amount -= vented;
+                    reports[node.d].vent(&node.resource, vented);
                 }
             }
 
             //Update block
-            if let Some(mut resource) = self.blocks[node.i].resources.get_mut(&node.resource) {
+            if let Some(resource) = deck.blocks[node.i].resources.get_mut(&node.resource) {
                 resource.amount = node.amount;
             }
         }
 
-        redraw
+        reports
+    }
+}
+
+/// Run a process on a block for `dt` seconds, at the fastest rate its inputs and outputs allow
+fn run_process(process: &Process, block: &mut Block, dt: f64, report: &mut FlowReport) {
+    let mut fraction: f64 = 1.0;
+    for (name, rate) in process.consumes.iter() {
+        match block.resources.get(name.as_str()) {
+            Some(resource) => if rate * dt > 0.0 {
+                fraction = fraction.min(resource.amount/(rate * dt));
+            },
+            None => return
+        }
+    }
+    for (name, rate) in process.produces.iter() {
+        match block.resources.get(name.as_str()) {
+            Some(resource) => if rate * dt > 0.0 {
+                fraction = fraction.min((resource.capacity - resource.amount)/(rate * dt));
+            },
+            None => return
+        }
+    }
+
+    if fraction <= 0.0 {
+        return;
+    }
+
+    for (name, rate) in process.consumes.iter() {
+        let amount = rate * dt * fraction;
+        if let Some(resource) = block.resources.get_mut(name.as_str()) {
+            resource.amount = (resource.amount - amount).max(0.0);
+        }
+        report.consume(name, amount);
+    }
+    for (name, rate) in process.produces.iter() {
+        let amount = rate * dt * fraction;
+        if let Some(resource) = block.resources.get_mut(name.as_str()) {
+            resource.amount = (resource.amount + amount).min(resource.capacity);
+        }
+        report.produce(name, amount);
+    }
+}
+
+/// The stored form of a deck, which is indexed after loading
+#[derive(Deserialize)]
+struct DeckData<'a> {
+    name: String,
+    blocks: Vec<Block<'a>>
+}
+
+impl<'a> From<DeckData<'a>> for Deck<'a> {
+    fn from(data: DeckData<'a>) -> Deck<'a> {
+        Deck::new(data.name, data.blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use kind::BlockRegistry;
+
+    use super::Deck;
+
+    /// Largest difference allowed between a change in a total and the report, from rounding
+    const EPSILON: f64 = 1e-9;
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap()
+    }
+
+    fn block<'a>(x: isize, y: isize, kind: &str, registry: &BlockRegistry) -> Block<'a> {
+        Block::from_kind(x, y, kind, registry).unwrap()
+    }
+
+    /// A tank full of one resource
+    fn tank<'a>(x: isize, y: isize, resource: &str, registry: &BlockRegistry) -> Block<'a> {
+        let kind = registry.get("Tank").unwrap();
+        let variant = kind.variants.iter().find(|variant| variant.resources.contains_key(resource)).unwrap();
+        Block::from_variant(x, y, kind, variant)
+    }
+
+    /// Run some ticks, checking that each deck total changes by exactly what its report accounts for
+    fn check_ticks(decks: &mut [Deck], registry: &BlockRegistry, ticks: usize) {
+        for tick in 0 .. ticks {
+            let before: Vec<_> = decks.iter().map(|deck| deck.totals()).collect();
+            let reports = Deck::update_all(decks, 0.01, registry);
+            for (d, report) in reports.iter().enumerate() {
+                let after = decks[d].totals();
+                for resource in before[d].keys().chain(after.keys()) {
+                    let change = after.get(resource).cloned().unwrap_or(0.0) - before[d].get(resource).cloned().unwrap_or(0.0);
+                    let net = report.net(resource);
+                    assert!((change - net).abs() < EPSILON, "tick {}, deck {}: {} changed by {}, but the report gives {}", tick, d, resource, change, net);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_conserves_resources() {
+        let registry = registry();
+        // Air runs from a tank to a vent, fuel to a reactor, and the room leaks from its open end
+        let mut deck = Deck::new("Test".to_string(), vec![
+            tank(0, 0, "air", &registry),
+            tank(0, 1, "fuel", &registry),
+            block(1, 0, "Conduit", &registry),
+            block(1, 1, "Conduit", &registry),
+            block(2, 1, "Reactor", &registry),
+            block(2, 0, "Deck", &registry),
+            block(2, 0, "Vent", &registry),
+            block(3, 0, "Deck", &registry),
+            block(3, 0, "Man", &registry),
+            block(4, 0, "Deck", &registry)
+        ]);
+
+        check_ticks(::std::slice::from_mut(&mut deck), &registry, 20);
+
+        let report = deck.update(0.01, &registry);
+        assert!(report.produced.contains_key("free_air"));
+        assert!(report.produced.contains_key("electricity"));
+        assert!(report.vented.contains_key("free_air"));
+    }
+
+    #[test]
+    fn update_all_conserves_resources() {
+        let registry = registry();
+        // Air is piped up a vertical conduit to a vent on the deck above
+        let mut decks = vec![
+            Deck::new("Lower".to_string(), vec![
+                tank(0, 0, "air", &registry),
+                block(1, 0, "VerticalConduit", &registry)
+            ]),
+            Deck::new("Upper".to_string(), vec![
+                block(1, 0, "VerticalConduit", &registry),
+                block(2, 0, "Deck", &registry),
+                block(2, 0, "Vent", &registry)
+            ])
+        ];
+
+        check_ticks(&mut decks, &registry, 20);
+
+        let reports = Deck::update_all(&mut decks, 0.01, &registry);
+        assert!(reports[0].exported.contains_key("air"));
+        assert!(reports[1].imported.contains_key("air"));
     }
 }
@@ -0,0 +1,290 @@
+use block::{Block, BlockResource};
+use deck::Deck;
+use error::{Error, Result};
+use ship::Ship;
+
+use std::mem;
+
+/// Most edits kept for undo
+pub const MAX_HISTORY: usize = 1000;
+
+/// A change made to a ship by the editor, as opposed to by the simulation.
+/// Applying an edit returns its inverse, which undoes it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Edit<'a> {
+    /// Insert a block at `index` in the blocks of a deck
+    AddBlock { deck: usize, index: usize, block: Block<'a> },
+    RemoveBlock { deck: usize, index: usize },
+    MoveBlock { deck: usize, index: usize, x: isize, y: isize },
+    /// Set a resource of a block, or remove it if `value` is None
+    EditResource { deck: usize, index: usize, resource: String, value: Option<BlockResource> },
+    /// Open or close a door
+    ToggleBlock { deck: usize, index: usize },
+    /// Insert a deck at `index`
+    AddDeck { index: usize, deck: Deck<'a> },
+    RemoveDeck { index: usize },
+    RenameDeck { index: usize, name: String },
+    /// Insert a copy of a deck above it
+    DuplicateDeck { index: usize },
+    /// Move a deck to another position in the stack
+    MoveDeck { from: usize, to: usize },
+    /// Several edits applied in order, and undone together
+    Group(Vec<Edit<'a>>)
+}
+
+fn check_deck(ship: &Ship, deck: usize) -> Result<()> {
+    if deck < ship.decks.len() {
+        Ok(())
+    } else {
+        Err(Error::InvalidEdit(format!("no deck {}", deck)))
+    }
+}
+
+fn check_block(ship: &Ship, deck: usize, index: usize) -> Result<()> {
+    check_deck(ship, deck)?;
+    if index < ship.decks[deck].blocks.len() {
+        Ok(())
+    } else {
+        Err(Error::InvalidEdit(format!("no block {} on deck {}", index, deck)))
+    }
+}
+
+impl<'a> Edit<'a> {
+    /// Apply the edit to a ship, returning the edit that undoes it
+    pub fn apply(self, ship: &mut Ship<'a>) -> Result<Edit<'a>> {
+        match self {
+            Edit::AddBlock { deck, index, block } => {
+                check_deck(ship, deck)?;
+                if index > ship.decks[deck].blocks.len() {
+                    return Err(Error::InvalidEdit(format!("cannot insert block {} on deck {}", index, deck)));
+                }
+                ship.decks[deck].insert_block(index, block);
+                Ok(Edit::RemoveBlock { deck: deck, index: index })
+            },
+            Edit::RemoveBlock { deck, index } => {
+                check_block(ship, deck, index)?;
+                let block = ship.decks[deck].remove_block(index);
+                Ok(Edit::AddBlock { deck: deck, index: index, block: block })
+            },
+            Edit::MoveBlock { deck, index, x, y } => {
+                check_block(ship, deck, index)?;
+                let (old_x, old_y) = {
+                    let block = &ship.decks[deck].blocks[index];
+                    (block.x, block.y)
+                };
+                ship.decks[deck].move_block(index, x, y);
+                Ok(Edit::MoveBlock { deck: deck, index: index, x: old_x, y: old_y })
+            },
+            Edit::EditResource { deck, index, resource, value } => {
+                check_block(ship, deck, index)?;
+                let resources = &mut ship.decks[deck].blocks[index].resources;
+                let old_value = match value {
+                    Some(value) => resources.insert(resource.clone().into(), value),
+                    None => resources.remove(resource.as_str())
+                };
+                Ok(Edit::EditResource { deck: deck, index: index, resource: resource, value: old_value })
+            },
+            Edit::ToggleBlock { deck, index } => {
+                check_block(ship, deck, index)?;
+                if ship.decks[deck].blocks[index].toggle() {
+                    Ok(Edit::ToggleBlock { deck: deck, index: index })
+                } else {
+                    Err(Error::InvalidEdit(format!("block {} on deck {} is not a door", index, deck)))
+                }
+            },
+            Edit::AddDeck { index, deck } => {
+                ship.insert_deck(index, deck)?;
+                Ok(Edit::RemoveDeck { index: index })
+            },
+            Edit::RemoveDeck { index } => {
+                let deck = ship.remove_deck(index)?;
+                Ok(Edit::AddDeck { index: index, deck: deck })
+            },
+            Edit::RenameDeck { index, name } => {
+                let old = ship.rename_deck(index, name)?;
+                Ok(Edit::RenameDeck { index: index, name: old })
+            },
+            Edit::DuplicateDeck { index } => {
+                let copy = ship.duplicate_deck(index)?;
+                Ok(Edit::RemoveDeck { index: copy })
+            },
+            Edit::MoveDeck { from, to } => {
+                ship.move_deck(from, to)?;
+                Ok(Edit::MoveDeck { from: to, to: from })
+            },
+            Edit::Group(edits) => {
+                let mut inverses = Vec::with_capacity(edits.len());
+                for edit in edits {
+                    match edit.apply(ship) {
+                        Ok(inverse) => inverses.push(inverse),
+                        Err(err) => {
+                            // Roll back the edits already applied, so the ship is unchanged.
+                            // An inverse always applies to the state its edit produced.
+                            while let Some(inverse) = inverses.pop() {
+                                inverse.apply(ship).expect("failed to roll back edit group");
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                inverses.reverse();
+                Ok(Edit::Group(inverses))
+            }
+        }
+    }
+
+    /// Remove several blocks from a deck, in any order
+    pub fn remove_blocks(deck: usize, indexes: &[usize]) -> Edit<'a> {
+        let mut indexes = indexes.to_vec();
+        indexes.sort();
+        indexes.dedup();
+        // Remove from the end, so the indexes still to be removed do not shift
+        Edit::Group(indexes.into_iter().rev().map(|index| Edit::RemoveBlock { deck: deck, index: index }).collect())
+    }
+
+    /// Add several blocks to the end of a deck with `count` blocks
+    pub fn add_blocks(deck: usize, count: usize, blocks: Vec<Block<'a>>) -> Edit<'a> {
+        Edit::Group(blocks.into_iter().enumerate().map(|(i, block)| Edit::AddBlock { deck: deck, index: count + i, block: block }).collect())
+    }
+
+    /// Move several blocks of a deck, given as (index, x, y)
+    pub fn move_blocks(deck: usize, moves: &[(usize, isize, isize)]) -> Edit<'a> {
+        Edit::Group(moves.iter().map(|&(index, x, y)| Edit::MoveBlock { deck: deck, index: index, x: x, y: y }).collect())
+    }
+}
+
+/// Undo and redo stacks of edits, each with the `current_deck` to show once it is applied
+#[derive(Clone, Debug)]
+pub struct History<'a> {
+    undo: Vec<(Edit<'a>, usize)>,
+    redo: Vec<(Edit<'a>, usize)>,
+    /// Length of `undo` when the ship was last saved, or None if that state can no longer be reached
+    saved: Option<usize>,
+    /// Every edit applied to the ship since the log was last taken, including undos, redos and previews,
+    /// or None if not logging
+    log: Option<Vec<Edit<'a>>>
+}
+
+/// Apply an edit to a ship, adding it to the log if there is one
+fn apply_logged<'a>(ship: &mut Ship<'a>, edit: Edit<'a>, log: &mut Option<Vec<Edit<'a>>>) -> Result<Edit<'a>> {
+    match *log {
+        Some(ref mut log) => {
+            let inverse = edit.clone().apply(ship)?;
+            log.push(edit);
+            Ok(inverse)
+        },
+        None => edit.apply(ship)
+    }
+}
+
+/// Apply an edit from one stack, moving its inverse to the other and restoring the deck it was made on
+fn transfer<'a>(ship: &mut Ship<'a>, from: &mut Vec<(Edit<'a>, usize)>, to: &mut Vec<(Edit<'a>, usize)>, log: &mut Option<Vec<Edit<'a>>>) -> Result<bool> {
+    match from.pop() {
+        Some((edit, current_deck)) => {
+            let previous_deck = ship.current_deck;
+            let inverse = apply_logged(ship, edit, log)?;
+            if current_deck < ship.decks.len() {
+                ship.current_deck = current_deck;
+            }
+            to.push((inverse, previous_deck));
+            Ok(true)
+        },
+        None => Ok(false)
+    }
+}
+
+impl<'a> Default for History<'a> {
+    fn default() -> History<'a> {
+        History::new()
+    }
+}
+
+impl<'a> History<'a> {
+    pub fn new() -> History<'a> {
+        History {
+            undo: vec![],
+            redo: vec![],
+            saved: Some(0),
+            log: None
+        }
+    }
+
+    /// Apply a new edit, making it undoable and clearing the redo stack
+    pub fn apply(&mut self, ship: &mut Ship<'a>, edit: Edit<'a>) -> Result<()> {
+        let current_deck = ship.current_deck;
+        let inverse = apply_logged(ship, edit, &mut self.log)?;
+        // The saved state was undone, and is lost with the redo stack
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        if self.undo.len() >= MAX_HISTORY {
+            self.undo.remove(0);
+            self.saved = self.saved.and_then(|saved| saved.checked_sub(1));
+        }
+        self.undo.push((inverse, current_deck));
+        self.redo.clear();
+        Ok(())
+    }
+
+    /// Undo the last edit. Returns false if there is nothing to undo.
+    pub fn undo(&mut self, ship: &mut Ship<'a>) -> Result<bool> {
+        transfer(ship, &mut self.undo, &mut self.redo, &mut self.log)
+    }
+
+    /// Redo the last undone edit. Returns false if there is nothing to redo.
+    pub fn redo(&mut self, ship: &mut Ship<'a>) -> Result<bool> {
+        transfer(ship, &mut self.redo, &mut self.undo, &mut self.log)
+    }
+
+    /// Apply an edit that is not kept for undo, such as blocks following the mouse during a drag.
+    /// It is still logged, as the simulation may run before it is replaced by an undoable edit.
+    pub fn preview(&mut self, ship: &mut Ship<'a>, edit: Edit<'a>) -> Result<()> {
+        apply_logged(ship, edit, &mut self.log).map(|_| ())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        ! self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        ! self.redo.is_empty()
+    }
+
+    /// Forget all edits, such as after loading a different ship, which is then unmodified
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.saved = Some(0);
+    }
+
+    /// Remember that the ship was saved as it is now
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    /// Forget that the ship was saved, such as after recovering edits that were never saved
+    pub fn mark_unsaved(&mut self) {
+        self.saved = None;
+    }
+
+    /// True if there are edits since the ship was loaded or saved
+    pub fn is_dirty(&self) -> bool {
+        self.saved != Some(self.undo.len())
+    }
+
+    /// Start logging every edit applied to the ship, such as to record a session for replay
+    pub fn start_log(&mut self) {
+        if self.log.is_none() {
+            self.log = Some(vec![]);
+        }
+    }
+
+    pub fn stop_log(&mut self) {
+        self.log = None;
+    }
+
+    /// The edits applied since the log was started or last taken, in order
+    pub fn take_log(&mut self) -> Vec<Edit<'a>> {
+        self.log.as_mut().map_or(vec![], mem::take)
+    }
+}
@@ -0,0 +1,527 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use edit::{Edit, History};
+use error::{Error, Result};
+use flow::FlowReport;
+use kind::BlockRegistry;
+use replay::{Recording, Replay};
+use save;
+use ship::Ship;
+use simulation::Simulation;
+use telemetry::{Sample, Watch};
+
+/// Most real milliseconds the simulation thread waits for a command before running the ticks that are due
+pub const WAIT_MILLIS: u64 = 10;
+
+/// Fewest real milliseconds between snapshots published after running ticks, so the ship is not copied more often
+/// than it can be drawn. Snapshots after commands are published at once.
+pub const PUBLISH_MILLIS: u64 = 16;
+
+/// A request to the simulation thread, which owns the ship
+#[derive(Debug)]
+pub enum Command {
+    /// Apply an edit that can be undone
+    Edit(Edit<'static>),
+    /// Apply an edit that is not kept for undo, such as to show blocks while they are dragged
+    Preview(Edit<'static>),
+    /// Undo a preview with `revert`, then apply `edit` so that it can be undone, with no tick in between,
+    /// such as to finish a drag as one move
+    CommitPreview { revert: Edit<'static>, edit: Edit<'static> },
+    Undo,
+    Redo,
+    /// Show another deck
+    SetDeck(usize),
+    /// Replace the ship, forgetting the undo history and the watches, and finishing any recording.
+    /// `saved` is false if the ship has edits that are not in its file, such as one recovered from an autosave.
+    Load { ship: Ship<'static>, saved: bool },
+    /// Save the ship to a path, keeping backups as `save` does, and remember that it was saved
+    Save(String),
+    TogglePause,
+    /// Run a single tick, pausing if running
+    Step,
+    Faster,
+    Slower,
+    /// Replace the tiles whose totals are sampled, in the order of `Telemetry::watches`
+    Watches(Vec<Watch>),
+    /// Go to a tick of the replay, pausing playback. Does nothing unless replaying.
+    Seek(u64),
+    /// Record every tick and edit from now on, to be written to a path when recording stops
+    Record(String),
+    /// Finish the recording, if there is one, and write it
+    StopRecording,
+    /// Stop the thread, writing any recording that was not stopped
+    Quit
+}
+
+/// Where a replay is
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayStatus {
+    pub tick: u64,
+    /// Ticks in the whole recording
+    pub ticks: u64,
+    /// See `Replay::matches_end`
+    pub matches_end: Option<bool>,
+    /// Why playback stopped before the end, if an edit could not be applied
+    pub error: Option<String>
+}
+
+/// The state of the simulation thread after a command or a run of ticks. It is never changed once published.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// Increases with every snapshot, so a new one can be noticed without comparing ships
+    pub generation: u64,
+    pub ship: Arc<Ship<'static>>,
+    /// Reports of the last tick, one per deck
+    pub flows: Vec<FlowReport>,
+    /// Speed multiplier of the simulation
+    pub speed: f64,
+    pub paused: bool,
+    /// True if there are edits that have not been saved
+    pub dirty: bool,
+    pub can_undo: bool,
+    pub can_redo: bool,
+    pub recording: bool,
+    /// None unless replaying
+    pub replay: Option<ReplayStatus>
+}
+
+/// A recording in progress, with the path it will be written to and the tick it started at
+struct Recorder {
+    path: String,
+    recording: Recording<'static>,
+    start: u64
+}
+
+/// Everything owned by the simulation thread
+struct State {
+    ship: Ship<'static>,
+    history: History<'static>,
+    simulation: Simulation,
+    registry: Arc<BlockRegistry>,
+    /// Played back at the speed of the simulation, instead of running it
+    replay: Option<Replay<'static>>,
+    /// Why the replay stopped before the end
+    replay_error: Option<String>,
+    recorder: Option<Recorder>,
+    watches: Vec<Watch>,
+    /// Ticks between samples
+    interval: u64,
+    flows: Vec<FlowReport>,
+    generation: u64,
+    /// The ship in the last snapshot, shared by the next one unless the ship has changed since
+    shown: Arc<Ship<'static>>,
+    /// True if the ship has changed since the last snapshot
+    changed: bool
+}
+
+impl State {
+    fn snapshot(&mut self) -> Arc<Snapshot> {
+        self.generation += 1;
+        if self.changed {
+            self.shown = Arc::new(self.ship.clone());
+            self.changed = false;
+        }
+        Arc::new(Snapshot {
+            generation: self.generation,
+            ship: self.shown.clone(),
+            flows: self.flows.clone(),
+            speed: self.simulation.speed(),
+            paused: self.simulation.paused,
+            dirty: self.history.is_dirty(),
+            can_undo: self.history.can_undo(),
+            can_redo: self.history.can_redo(),
+            recording: self.recorder.is_some(),
+            replay: self.replay.as_ref().map(|replay| ReplayStatus {
+                tick: replay.tick(),
+                ticks: replay.ticks(),
+                matches_end: replay.matches_end(),
+                error: self.replay_error.clone()
+            })
+        })
+    }
+
+    /// Show the replayed ship, staying on the same deck
+    fn show_replay(&mut self) {
+        if let Some(ref replay) = self.replay {
+            let current_deck = self.ship.current_deck;
+            self.ship = replay.ship.clone();
+            self.ship.current_deck = current_deck.min(self.ship.decks.len().saturating_sub(1));
+        }
+    }
+
+    /// Add the edits logged since the last command to the recording
+    fn record(&mut self) {
+        if let Some(ref mut recorder) = self.recorder {
+            let tick = self.simulation.ticks - recorder.start;
+            for edit in self.history.take_log() {
+                recorder.recording.push(tick, edit);
+            }
+        }
+    }
+
+    fn stop_recording(&mut self) -> Result<()> {
+        self.record();
+        self.history.stop_log();
+        match self.recorder.take() {
+            Some(mut recorder) => {
+                recorder.recording.finish(self.simulation.ticks - recorder.start, &self.ship);
+                recorder.recording.save(&recorder.path)
+            },
+            None => Ok(())
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> Result<()> {
+        // Assume the ship changed even if the command fails part way, as a group of edits may
+        if ! matches!(command, Command::Save(_) | Command::TogglePause | Command::Step | Command::Faster | Command::Slower | Command::Watches(_) | Command::Record(_) | Command::StopRecording | Command::Quit) {
+            self.changed = true;
+        }
+
+        if self.replay.is_some() {
+            match command {
+                Command::Edit(_) | Command::Preview(_) | Command::CommitPreview { .. } | Command::Undo | Command::Redo | Command::Load { .. } | Command::Save(_) | Command::Record(_) => {
+                    return Err(Error::InvalidEdit("a replay cannot be edited".to_string()));
+                },
+                _ => ()
+            }
+        }
+
+        match command {
+            Command::Edit(edit) => self.history.apply(&mut self.ship, edit)?,
+            Command::Preview(edit) => self.history.preview(&mut self.ship, edit)?,
+            Command::CommitPreview { revert, edit } => {
+                self.history.preview(&mut self.ship, revert)?;
+                self.history.apply(&mut self.ship, edit)?;
+            },
+            Command::Undo => {
+                self.history.undo(&mut self.ship)?;
+            },
+            Command::Redo => {
+                self.history.redo(&mut self.ship)?;
+            },
+            Command::SetDeck(deck) => if deck < self.ship.decks.len() {
+                self.ship.current_deck = deck;
+            } else {
+                return Err(Error::InvalidEdit(format!("deck {} does not exist", deck)));
+            },
+            Command::Load { ship, saved } => {
+                // A recording only covers the ship it started with
+                self.stop_recording()?;
+                self.ship = ship;
+                self.history.clear();
+                if ! saved {
+                    self.history.mark_unsaved();
+                }
+                self.watches.clear();
+                self.flows.clear();
+            },
+            Command::Save(path) => {
+                save(&path, &self.ship)?;
+                self.history.mark_saved();
+            },
+            Command::TogglePause => self.simulation.toggle_pause(),
+            Command::Step => self.simulation.step(),
+            Command::Faster => self.simulation.faster(),
+            Command::Slower => self.simulation.slower(),
+            Command::Watches(watches) => self.watches = watches,
+            Command::Seek(tick) => {
+                self.simulation.pause();
+                if let Some(ref mut replay) = self.replay {
+                    self.replay_error = None;
+                    if let Err(err) = replay.seek(tick) {
+                        self.replay_error = Some(err.to_string());
+                    }
+                }
+                self.show_replay();
+            },
+            Command::Record(path) => {
+                self.stop_recording()?;
+                self.history.start_log();
+                self.recorder = Some(Recorder {
+                    path: path,
+                    recording: Recording::new(&self.ship, self.simulation.timestep, &self.registry),
+                    start: self.simulation.ticks
+                });
+            },
+            Command::StopRecording => self.stop_recording()?,
+            Command::Quit => ()
+        }
+
+        // Commands are handled between ticks, so every edit is recorded at the tick it was made
+        self.record();
+        Ok(())
+    }
+
+    /// Run the ticks due after `elapsed` real seconds, sending a sample every `interval` ticks.
+    /// Returns true if there is anything new to draw.
+    fn advance(&mut self, elapsed: f64, samples: &Sender<Sample>) -> bool {
+        let interval = self.interval;
+        let watches = &self.watches;
+        let sample = |ship: &Ship, tick: u64, time: f64| if tick.is_multiple_of(interval) {
+            // The receiver is only gone while the thread is stopping
+            let _ = samples.send(Sample::new(ship, watches, tick, time));
+        };
+
+        let reports = match self.replay {
+            Some(ref mut replay) => {
+                let mut reports = vec![];
+                for _ in 0 .. self.simulation.due(elapsed) {
+                    match replay.step() {
+                        Ok(Some(tick)) => {
+                            sample(&replay.ship, replay.tick(), replay.time());
+                            reports.push(tick);
+                        },
+                        Ok(None) => break,
+                        Err(err) => {
+                            // The recording cannot be played past a broken edit
+                            self.replay_error = Some(err.to_string());
+                            self.simulation.pause();
+                            break;
+                        }
+                    }
+                }
+                reports
+            },
+            None => self.simulation.advance_with(&mut self.ship, &self.registry, elapsed, sample)
+        };
+
+        let replaying = self.replay.is_some();
+        if replaying && ! reports.is_empty() {
+            self.show_replay();
+        }
+        // Ticks in which nothing moved leave nothing new to draw, unless they move a replay along
+        let changed = replaying && ! reports.is_empty() || reports.iter().any(|tick| tick.iter().any(|report| ! report.is_empty()));
+        if let Some(tick) = reports.into_iter().last() {
+            self.flows = tick;
+        }
+        self.changed |= changed;
+        changed
+    }
+}
+
+fn stopped() -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "the simulation thread has stopped"))
+}
+
+/// A command, with where to send the snapshot published after it if the sender is waiting
+type Message = (Command, Option<Sender<Result<Arc<Snapshot>>>>);
+
+fn run(mut state: State, commands: Receiver<Message>, latest: Arc<Mutex<Arc<Snapshot>>>, samples: Sender<Sample>, errors: Sender<Error>) {
+    let publish = |state: &mut State| {
+        let snapshot = state.snapshot();
+        *latest.lock().unwrap() = snapshot.clone();
+        snapshot
+    };
+
+    let mut last = Instant::now();
+    let mut published = Instant::now();
+    // Set when ticks moved something that has not been published yet
+    let mut unpublished = false;
+    loop {
+        // Wait for a command until ticks are due, then take every other command that is waiting
+        let mut messages = match commands.recv_timeout(Duration::from_millis(WAIT_MILLIS)) {
+            Ok(message) => vec![message],
+            Err(RecvTimeoutError::Timeout) => vec![],
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+        messages.extend(commands.try_iter());
+
+        let handled = ! messages.is_empty();
+        for (command, reply) in messages {
+            let quit = matches!(command, Command::Quit);
+            let result = state.handle(command);
+            match reply {
+                // Nobody may be waiting for the reply any more
+                Some(reply) => {
+                    let _ = reply.send(result.map(|()| publish(&mut state)));
+                },
+                None => if let Err(err) = result {
+                    let _ = errors.send(err);
+                }
+            }
+            if quit {
+                let _ = state.stop_recording();
+                return;
+            }
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(last);
+        last = now;
+        unpublished |= state.advance(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64/1000000000.0, &samples);
+
+        if handled || unpublished && published.elapsed() >= Duration::from_millis(PUBLISH_MILLIS) {
+            publish(&mut state);
+            published = Instant::now();
+            unpublished = false;
+        }
+    }
+    // Errors writing the recording cannot be reported once the handle is gone
+    let _ = state.stop_recording();
+}
+
+/// A thread that owns a ship and runs its simulation. It takes commands over a channel,
+/// and publishes a snapshot after each batch of commands and, at most every `PUBLISH_MILLIS`, after ticks in which something moved.
+/// Publishing swaps an `Arc`, so drawing a snapshot never holds up the simulation, nor the reverse,
+/// and a snapshot shares the ship of the one before it if the ship has not changed.
+pub struct Engine {
+    commands: Sender<Message>,
+    latest: Arc<Mutex<Arc<Snapshot>>>,
+    samples: Receiver<Sample>,
+    errors: Receiver<Error>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Engine {
+    /// Start simulating a ship with a fixed timestep, sampling its totals every `interval` ticks
+    pub fn new(ship: Ship<'static>, registry: Arc<BlockRegistry>, timestep: f64, interval: u64) -> Engine {
+        Engine::spawn(ship, None, registry, timestep, interval)
+    }
+
+    /// Start playing back a recording at the speed of the simulation, with the block kinds it was recorded with,
+    /// sampling its totals every `interval` ticks
+    pub fn replay(replay: Replay<'static>, interval: u64) -> Engine {
+        let ship = replay.ship.clone();
+        let timestep = replay.recording().timestep;
+        let registry = Arc::new(replay.registry().clone());
+        Engine::spawn(ship, Some(replay), registry, timestep, interval)
+    }
+
+    fn spawn(ship: Ship<'static>, replay: Option<Replay<'static>>, registry: Arc<BlockRegistry>, timestep: f64, interval: u64) -> Engine {
+        let mut state = State {
+            shown: Arc::new(ship.clone()),
+            ship: ship,
+            history: History::new(),
+            simulation: Simulation::new(timestep),
+            registry: registry,
+            replay: replay,
+            replay_error: None,
+            recorder: None,
+            watches: Vec::new(),
+            interval: interval.max(1),
+            flows: Vec::new(),
+            generation: 0,
+            changed: false
+        };
+        let latest = Arc::new(Mutex::new(state.snapshot()));
+        let (commands, receiver) = mpsc::channel();
+        let (sample_sender, samples) = mpsc::channel();
+        let (error_sender, errors) = mpsc::channel();
+
+        let latest_thread = latest.clone();
+        let thread = thread::spawn(move || run(state, receiver, latest_thread, sample_sender, error_sender));
+
+        Engine {
+            commands: commands,
+            latest: latest,
+            samples: samples,
+            errors: errors,
+            thread: Some(thread)
+        }
+    }
+
+    /// The most recently published snapshot
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// Send a command without waiting for it. Its effect shows in a later snapshot, and if it fails, the error is
+    /// returned by `errors`. Commands are handled between ticks, in the order they were sent.
+    /// Fails only if the thread has stopped.
+    pub fn send(&self, command: Command) -> Result<()> {
+        self.commands.send((command, None)).map_err(|_| stopped())
+    }
+
+    /// Send a command and wait for it to be handled, returning the snapshot published after it or its error.
+    /// This waits for any ticks being run, so it is meant for commands whose result is needed at once,
+    /// such as an edit that the indexes of the next edit depend on, or `Save`.
+    pub fn call(&self, command: Command) -> Result<Arc<Snapshot>> {
+        let (reply, receiver) = mpsc::channel();
+        self.commands.send((command, Some(reply))).map_err(|_| stopped())?;
+        receiver.recv().map_err(|_| stopped())?
+    }
+
+    /// Errors of commands sent with `send` since the last call, oldest first
+    pub fn errors(&self) -> Vec<Error> {
+        self.errors.try_iter().collect()
+    }
+
+    /// Samples taken since the last call, oldest first
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.try_iter().collect()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.commands.send((Command::Quit, None));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use block::Block;
+    use edit::Edit;
+    use kind::BlockRegistry;
+    use ship::Ship;
+    use simulation::TIMESTEP;
+
+    use super::{Command, Engine};
+
+    fn engine() -> (Engine, Arc<BlockRegistry>) {
+        let registry = Arc::new(BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap());
+        let engine = Engine::new(Ship::new("Test".to_string()), registry.clone(), TIMESTEP, 10);
+        // Paused, so only commands change the ship
+        engine.send(Command::TogglePause).unwrap();
+        (engine, registry)
+    }
+
+    fn add_floor(x: isize, registry: &BlockRegistry) -> Command {
+        Command::Edit(Edit::AddBlock { deck: 0, index: 0, block: Block::from_kind(x, 0, "Deck", registry).unwrap() })
+    }
+
+    #[test]
+    fn sent_edits_show_in_a_later_snapshot() {
+        let (engine, registry) = engine();
+        engine.send(add_floor(0, &registry)).unwrap();
+        engine.send(add_floor(1, &registry)).unwrap();
+
+        // Commands are handled in order, so this waits for both edits
+        let snapshot = engine.call(Command::Faster).unwrap();
+        assert_eq!(snapshot.ship.decks[0].blocks.len(), 2);
+        assert!(snapshot.paused && snapshot.can_undo && snapshot.dirty);
+        assert!(engine.errors().is_empty());
+    }
+
+    #[test]
+    fn sent_errors_are_returned_later() {
+        let (engine, _registry) = engine();
+        engine.send(Command::Edit(Edit::RemoveBlock { deck: 0, index: 5 })).unwrap();
+        engine.call(Command::Faster).unwrap();
+        assert_eq!(engine.errors().len(), 1);
+
+        assert!(engine.call(Command::SetDeck(3)).is_err());
+        assert!(engine.errors().is_empty());
+    }
+
+    #[test]
+    fn snapshots_share_an_unchanged_ship() {
+        let (engine, registry) = engine();
+        let a = engine.call(Command::Faster).unwrap();
+        let b = engine.call(Command::Slower).unwrap();
+        assert!(b.generation > a.generation);
+        assert!(Arc::ptr_eq(&a.ship, &b.ship));
+
+        let c = engine.call(add_floor(0, &registry)).unwrap();
+        assert!(! Arc::ptr_eq(&b.ship, &c.ship));
+    }
+}
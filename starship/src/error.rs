@@ -0,0 +1,100 @@
+use serde_json;
+use serde_json::error::Category;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// A problem that makes a loaded ship unsafe to simulate or edit
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// The ship has no deck at `current_deck`, which includes having no decks at all
+    CurrentDeckOutOfRange { current_deck: usize, decks: usize },
+    /// Two blocks of the same kind share a tile. Blocks of different kinds may be stacked.
+    DuplicateBlock { deck: usize, x: isize, y: isize, kind: String },
+    /// A block has more of a resource than it can hold
+    OverCapacity { deck: usize, x: isize, y: isize, resource: String, amount: f64, capacity: f64 },
+    /// A block has a resource it cannot hold any of, so its pressure is undefined
+    ZeroCapacity { deck: usize, x: isize, y: isize, resource: String },
+    /// A block is not in the block registry
+    UnknownKind { deck: usize, x: isize, y: isize, kind: String }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::CurrentDeckOutOfRange { current_deck, decks } => write!(f, "current_deck {} is out of range for {} decks", current_deck, decks),
+            ValidationError::DuplicateBlock { deck, x, y, ref kind } => write!(f, "deck {} at {}, {}: more than one {}", deck, x, y, kind),
+            ValidationError::OverCapacity { deck, x, y, ref resource, amount, capacity } => write!(f, "deck {} at {}, {}: {} amount {} is over its capacity {}", deck, x, y, resource, amount, capacity),
+            ValidationError::ZeroCapacity { deck, x, y, ref resource } => write!(f, "deck {} at {}, {}: {} has no capacity", deck, x, y, resource),
+            ValidationError::UnknownKind { deck, x, y, ref kind } => write!(f, "deck {} at {}, {}: unknown block kind {}", deck, x, y, kind)
+        }
+    }
+}
+
+/// An error loading, saving or validating a ship
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file is not valid JSON. Lines and columns start at 1.
+    Parse { line: usize, column: usize, message: String },
+    /// The file is valid JSON, but not a ship in a supported format version
+    Schema(String),
+    /// The ship loaded, but has the listed problems
+    Validation(Vec<ValidationError>),
+    /// An edit refers to a deck, block or resource that does not exist. The ship is unchanged.
+    InvalidEdit(String)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Parse { line, column, ref message } => write!(f, "line {} column {}: {}", line, column, message),
+            Error::Schema(ref message) => write!(f, "{}", message),
+            Error::Validation(ref errors) => {
+                write!(f, "{} validation errors", errors.len())?;
+                for err in errors.iter() {
+                    write!(f, "\n    {}", err)?;
+                }
+                Ok(())
+            },
+            Error::InvalidEdit(ref message) => write!(f, "invalid edit: {}", message)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        match err.classify() {
+            Category::Io => Error::Io(err.into()),
+            Category::Syntax | Category::Eof => {
+                // The position is kept separately, so drop it from the message
+                let suffix = format!(" at line {} column {}", err.line(), err.column());
+                let message = err.to_string();
+                Error::Parse {
+                    line: err.line(),
+                    column: err.column(),
+                    message: message.trim_end_matches(suffix.as_str()).to_string()
+                }
+            },
+            Category::Data => Error::Schema(err.to_string())
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
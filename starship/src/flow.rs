@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+/// An amount of a resource moved from one block to another on the same deck
+#[derive(Clone, Debug)]
+pub struct Transfer {
+    /// Index of the source block in `Deck::blocks`
+    pub from: usize,
+    /// Index of the destination block in `Deck::blocks`
+    pub to: usize,
+    pub resource: String,
+    pub amount: f64
+}
+
+/// The ledger of one deck update
+///
+/// Transfers conserve each resource. Anything created or destroyed during the update is listed in
+/// `produced`, `consumed` or `vented`, and anything moved to or from another deck in `exported` or
+/// `imported`, so that for every resource the change in the deck total is
+/// `produced - consumed - vented + imported - exported`.
+#[derive(Clone, Debug, Default)]
+pub struct FlowReport {
+    pub transfers: Vec<Transfer>,
+    /// Total amount transferred between blocks, by resource
+    pub moved: BTreeMap<String, f64>,
+    /// Amount lost to space, by resource
+    pub vented: BTreeMap<String, f64>,
+    /// Amount used up by blocks, by resource
+    pub consumed: BTreeMap<String, f64>,
+    /// Amount created by blocks, by resource
+    pub produced: BTreeMap<String, f64>,
+    /// Amount received from the decks above and below, by resource
+    pub imported: BTreeMap<String, f64>,
+    /// Amount sent to the decks above and below, by resource
+    pub exported: BTreeMap<String, f64>
+}
+
+fn add(map: &mut BTreeMap<String, f64>, resource: &str, amount: f64) {
+    *map.entry(resource.to_string()).or_insert(0.0) += amount;
+}
+
+impl FlowReport {
+    pub fn new() -> FlowReport {
+        FlowReport::default()
+    }
+
+    /// True if nothing changed during the update
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty() && self.vented.is_empty() && self.consumed.is_empty() && self.produced.is_empty()
+            && self.imported.is_empty() && self.exported.is_empty()
+    }
+
+    pub fn transfer(&mut self, from: usize, to: usize, resource: &str, amount: f64) {
+        self.transfers.push(Transfer {
+            from: from,
+            to: to,
+            resource: resource.to_string(),
+            amount: amount
+        });
+        add(&mut self.moved, resource, amount);
+    }
+
+    pub fn vent(&mut self, resource: &str, amount: f64) {
+        add(&mut self.vented, resource, amount);
+    }
+
+    pub fn consume(&mut self, resource: &str, amount: f64) {
+        add(&mut self.consumed, resource, amount);
+    }
+
+    pub fn produce(&mut self, resource: &str, amount: f64) {
+        add(&mut self.produced, resource, amount);
+    }
+
+    pub fn import(&mut self, resource: &str, amount: f64) {
+        add(&mut self.imported, resource, amount);
+    }
+
+    pub fn export(&mut self, resource: &str, amount: f64) {
+        add(&mut self.exported, resource, amount);
+    }
+
+    /// The expected change in the total amount of a resource
+    pub fn net(&self, resource: &str) -> f64 {
+        let get = |map: &BTreeMap<String, f64>| map.get(resource).cloned().unwrap_or(0.0);
+        get(&self.produced) - get(&self.consumed) - get(&self.vented) + get(&self.imported) - get(&self.exported)
+    }
+
+    /// Transfers combined into one per pair of blocks and resource, in the direction of the net flow.
+    /// Pairs whose transfers cancel out are left out.
+    pub fn net_transfers(&self) -> Vec<Transfer> {
+        let mut totals: BTreeMap<(usize, usize, &str), f64> = BTreeMap::new();
+        for transfer in self.transfers.iter() {
+            if transfer.from < transfer.to {
+                *totals.entry((transfer.from, transfer.to, &transfer.resource)).or_insert(0.0) += transfer.amount;
+            } else {
+                *totals.entry((transfer.to, transfer.from, &transfer.resource)).or_insert(0.0) -= transfer.amount;
+            }
+        }
+
+        totals.into_iter().filter(|&(_, amount)| amount != 0.0).map(|((a, b, resource), amount)| Transfer {
+            from: if amount > 0.0 { a } else { b },
+            to: if amount > 0.0 { b } else { a },
+            resource: resource.to_string(),
+            amount: amount.abs()
+        }).collect()
+    }
+}
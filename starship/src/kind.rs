@@ -0,0 +1,145 @@
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result, Read};
+use std::path::Path;
+
+use block::{BlockResource, Sensor};
+
+/// An alternate set of default resources for a block kind, placed with its own hotkey
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BlockVariant {
+    pub hotkey: char,
+    pub resources: BTreeMap<String, BlockResource>
+}
+
+/// A conversion run by every block of a kind, with rates in amount per second.
+/// It slows down when inputs run low or outputs fill up, and stops if the block lacks any of the resources.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Process {
+    pub consumes: BTreeMap<String, f64>,
+    pub produces: BTreeMap<String, f64>
+}
+
+/// The definition of a block kind, as stored in `res/blocks/<Kind>/data.json`.
+/// It is also written out whole, such as in a recording, which is read back with the same fields.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BlockKind {
+    pub name: String,
+    pub description: String,
+    /// Blocks movement of crew and objects. Nothing moves between tiles yet, so this only describes the kind.
+    pub solid: bool,
+    /// Stops free_air escaping to space through a tile that holds no air, so that the tile can bound a room.
+    /// Doors are only airtight while closed.
+    pub airtight: bool,
+    /// Behavior tags, such as "vent" or "storage"
+    pub tags: Vec<String>,
+    pub hotkey: Option<char>,
+    /// Default resources of a newly placed block
+    pub resources: BTreeMap<String, BlockResource>,
+    pub variants: Vec<BlockVariant>,
+    /// Conversions run every update, such as a reactor turning fuel into electricity
+    pub processes: Vec<Process>,
+    /// Default sensor configuration of a newly placed block
+    pub sensor: Option<Sensor>,
+    /// Whether a newly placed door is open, or None if this kind is not a door
+    pub open: Option<bool>
+}
+
+/// The on-disk form of data.json, where everything but the description is optional
+#[derive(Deserialize)]
+struct BlockKindData {
+    description: String,
+    solid: Option<bool>,
+    airtight: Option<bool>,
+    tags: Option<Vec<String>>,
+    hotkey: Option<char>,
+    resources: Option<BTreeMap<String, BlockResource>>,
+    variants: Option<Vec<BlockVariant>>,
+    processes: Option<Vec<Process>>,
+    sensor: Option<Sensor>,
+    open: Option<bool>
+}
+
+impl BlockKind {
+    fn load(name: String, path: &Path) -> Result<BlockKind> {
+        let mut file = File::open(path)?;
+
+        let mut string = String::new();
+        file.read_to_string(&mut string)?;
+
+        let data: BlockKindData = serde_json::from_str(&string).map_err(|err| Error::other(format!("{}: {}", path.display(), err)))?;
+        Ok(BlockKind {
+            name: name,
+            description: data.description,
+            solid: data.solid.unwrap_or(false),
+            airtight: data.airtight.unwrap_or(false),
+            tags: data.tags.unwrap_or_default(),
+            hotkey: data.hotkey,
+            resources: data.resources.unwrap_or_default(),
+            variants: data.variants.unwrap_or_default(),
+            processes: data.processes.unwrap_or_default(),
+            sensor: data.sensor,
+            open: data.open
+        })
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// All known block kinds, by name
+#[derive(Clone, Debug, Default)]
+pub struct BlockRegistry {
+    pub kinds: BTreeMap<String, BlockKind>
+}
+
+impl BlockRegistry {
+    pub fn new() -> BlockRegistry {
+        BlockRegistry::default()
+    }
+
+    /// Load every `<Kind>/data.json` in a directory, such as `res/blocks/`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BlockRegistry> {
+        let mut registry = BlockRegistry::new();
+
+        for entry_result in fs::read_dir(path)? {
+            let entry = entry_result?;
+            let mut data_path = entry.path();
+            data_path.push("data.json");
+            if data_path.is_file() {
+                let name = entry.file_name().into_string().map_err(|_| Error::new(ErrorKind::InvalidData, "block kind name is not valid UTF-8"))?;
+                let kind = BlockKind::load(name, &data_path)?;
+                registry.insert(kind);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, kind: BlockKind) {
+        self.kinds.insert(kind.name.clone(), kind);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BlockKind> {
+        self.kinds.get(name)
+    }
+
+    /// Find the kind, and possibly the variant, placed by a hotkey. Hotkeys are not case sensitive.
+    pub fn hotkey(&self, c: char) -> Option<(&BlockKind, Option<&BlockVariant>)> {
+        let c = c.to_ascii_uppercase();
+        for kind in self.kinds.values() {
+            if kind.hotkey.map(|h| h.to_ascii_uppercase()) == Some(c) {
+                return Some((kind, None));
+            }
+
+            for variant in kind.variants.iter() {
+                if variant.hotkey.to_ascii_uppercase() == c {
+                    return Some((kind, Some(variant)));
+                }
+            }
+        }
+        None
+    }
+}
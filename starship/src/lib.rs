@@ -1,29 +1,151 @@
-#![feature(question_mark)]
+// In the style this crate was written in, fields are initialized as `x: x` and some functions end with `return`
+#![allow(clippy::redundant_field_names, clippy::needless_return)]
 
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
 
-use rustc_serialize::json;
-use std::fs::File;
-use std::io::{Error, ErrorKind, Result, Read, Write};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub use error::{Error, Result, ValidationError};
 
 pub mod block;
+pub mod blueprint;
+pub mod cli;
 pub mod deck;
+pub mod edit;
+pub mod engine;
+pub mod error;
+pub mod flow;
+pub mod kind;
+pub mod migrate;
+pub mod room;
+pub mod replay;
+pub mod selection;
 pub mod ship;
+pub mod simulation;
+pub mod telemetry;
+
+/// Version of the ship file format written by `save`
+pub const FORMAT_VERSION: u64 = 2;
+
+/// Number of old versions of a ship file kept by `save`
+pub const BACKUPS: usize = 3;
 
-pub fn load(path: &str) -> Result<ship::Ship> {
+/// The top level of a ship file
+#[derive(Serialize)]
+struct ShipFile<'a, 'b: 'a> {
+    format_version: u64,
+    #[serde(flatten)]
+    ship: &'a ship::Ship<'b>
+}
+
+/// Load a ship file, upgrading it from any older format version.
+/// The ship is not validated, as that needs a block registry; see `Ship::validate`.
+pub fn load(path: &str) -> Result<ship::Ship<'static>> {
     let mut file = File::open(path)?;
 
     let mut string = String::new();
     file.read_to_string(&mut string)?;
 
-    let ship: ship::Ship = json::decode(&string).map_err(|err| Error::new(ErrorKind::Other, format!("{}", err)))?;
+    let mut value: serde_json::Value = serde_json::from_str(&string)?;
+    if migrate::version(&value).map_err(Error::Schema)? == FORMAT_VERSION {
+        // Decoding the text again, rather than the value, keeps the position of schema errors
+        let ship: ship::Ship<'static> = serde_json::from_str(&string)?;
+        return Ok(ship);
+    }
+
+    migrate::migrate(&mut value).map_err(Error::Schema)?;
+    let ship: ship::Ship<'static> = serde_json::from_value(value)?;
     Ok(ship)
 }
 
+/// Path of a numbered backup of a ship file, from 1 for the newest
+pub fn backup_path(path: &str, n: usize) -> String {
+    format!("{}.{}", path, n)
+}
+
+/// Save a ship file in the current format version, keeping `BACKUPS` old versions
 pub fn save(path: &str, ship: &ship::Ship) -> Result<()> {
-    let mut file = File::create(path)?;
+    save_with_backups(path, ship, BACKUPS)
+}
+
+/// Save a ship file in the current format version, moving the old file to `backup_path(path, 1)`
+/// and older backups up by one, keeping at most `backups` of them.
+/// The ship is written to a temporary file that replaces the old file in one rename,
+/// so a failed save leaves the old file as it was.
+pub fn save_with_backups(path: &str, ship: &ship::Ship, backups: usize) -> Result<()> {
+    let ship_file = ShipFile {
+        format_version: FORMAT_VERSION,
+        ship: ship
+    };
+    let string = serde_json::to_string_pretty(&ship_file)?;
+
+    let temp_path = format!("{}.tmp", path);
+    {
+        let mut file = File::create(&temp_path)?;
+        writeln!(file, "{}", string)?;
+        file.sync_all()?;
+    }
 
-    let encoder = json::as_pretty_json(ship);
-    write!(file, "{}", encoder)?;
+    if backups > 0 && Path::new(path).is_file() {
+        for n in (1 .. backups).rev() {
+            let backup = backup_path(path, n);
+            if Path::new(&backup).is_file() {
+                fs::rename(&backup, backup_path(path, n + 1))?;
+            }
+        }
+        // Copied rather than moved, so the ship file is never missing
+        fs::copy(path, backup_path(path, 1))?;
+    }
+
+    fs::rename(&temp_path, path)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use kind::BlockRegistry;
+
+    use super::{load, migrate, Error};
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn load_migrates_unversioned_ship() {
+        let path = fixture("ship-v0.json");
+
+        let mut value: serde_json::Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(migrate::version(&value), Ok(0));
+        migrate::migrate(&mut value).unwrap();
+        for block in value["decks"][0]["blocks"].as_array().unwrap() {
+            assert!(block.get("sensor").is_none());
+            assert_ne!(block.get("open"), Some(&serde_json::Value::Null));
+        }
+
+        let ship = load(&path).unwrap();
+        let registry = BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap();
+        ship.validate(&registry).unwrap();
+
+        let blocks = &ship.decks[0].blocks;
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].open, None);
+        assert_eq!(blocks[1].open, Some(true));
+        assert!(blocks.iter().all(|block| block.sensor.is_none()));
+    }
+
+    #[test]
+    fn load_rejects_newer_format_version() {
+        match load(&fixture("ship-newer.json")) {
+            Err(Error::Schema(_)) => (),
+            other => panic!("expected a schema error, got {:?}", other.map(|ship| ship.name))
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use serde_json::{Map, Value};
+
+use FORMAT_VERSION;
+
+/// Upgrades a ship file from one format version to the next. `MIGRATIONS[n]` turns version n into n + 1.
+pub type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+pub const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [
+    migrate_0,
+    migrate_1
+];
+
+/// Version 0 is every file written before `format_version` existed, by rustc_serialize.
+/// It has the same layout, but writes null for a missing sensor or open state.
+fn migrate_0(ship: &mut Map<String, Value>) -> Result<(), String> {
+    if let Some(&mut Value::Array(ref mut decks)) = ship.get_mut("decks") {
+        for deck in decks.iter_mut() {
+            if let Some(&mut Value::Array(ref mut blocks)) = deck.get_mut("blocks") {
+                for block in blocks.iter_mut() {
+                    if let Value::Object(ref mut block) = *block {
+                        for key in ["sensor", "open"].iter() {
+                            if block.get(*key) == Some(&Value::Null) {
+                                block.remove(*key);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 2 allows blocks at negative positions, so ships can grow in any direction.
+/// Every version 1 file is already valid.
+fn migrate_1(_ship: &mut Map<String, Value>) -> Result<(), String> {
+    Ok(())
+}
+
+/// The format version of a ship file, where a file without one is version 0
+pub fn version(ship: &Value) -> Result<u64, String> {
+    match ship.get("format_version") {
+        Some(version) => version.as_u64().ok_or_else(|| format!("format_version {} is not a whole number", version)),
+        None => Ok(0)
+    }
+}
+
+/// Upgrade a parsed ship file in place to `FORMAT_VERSION`, removing the version field
+pub fn migrate(ship: &mut Value) -> Result<(), String> {
+    let version = version(ship)?;
+    if version > FORMAT_VERSION {
+        return Err(format!("format_version {} is newer than the supported version {}", version, FORMAT_VERSION));
+    }
+
+    let ship = match *ship {
+        Value::Object(ref mut ship) => ship,
+        _ => return Err("ship file is not a JSON object".to_string())
+    };
+    ship.remove("format_version");
+
+    for migration in MIGRATIONS[version as usize ..].iter() {
+        migration(ship)?;
+    }
+    Ok(())
+}
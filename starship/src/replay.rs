@@ -0,0 +1,369 @@
+use serde_json;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use deck::Deck;
+use edit::Edit;
+use error::{Error, Result};
+use flow::FlowReport;
+use kind::{BlockKind, BlockRegistry};
+use ship::Ship;
+
+/// Version of the recording file format written by `Recording::save`
+pub const RECORDING_VERSION: u64 = 2;
+
+/// Ticks between the states first kept by `Replay`, so seeking backward only replays from the nearest one
+pub const KEYFRAME_TICKS: u64 = 500;
+
+/// Most states kept by `Replay`. When there would be more, every other one is dropped and the ticks between them doubled.
+pub const MAX_KEYFRAMES: usize = 64;
+
+/// An edit made after some number of ticks
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Event<'a> {
+    /// Ticks run before the edit was applied
+    pub tick: u64,
+    pub edit: Edit<'a>
+}
+
+/// A ship and every edit made to it while it ran, enough to run it again to the same state
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Recording<'a> {
+    pub format_version: u64,
+    /// Simulated seconds per tick
+    pub timestep: f64,
+    /// Every block kind when recording started. A replay runs with these rather than the files in `res/blocks/`,
+    /// which may have changed since, so that it reaches the same state.
+    pub kinds: Vec<BlockKind>,
+    /// The ship when recording started
+    pub ship: Ship<'a>,
+    /// Edits in the order they were applied
+    pub events: Vec<Event<'a>>,
+    /// Ticks run before recording stopped
+    pub ticks: u64,
+    /// The ship when recording stopped, to check a replay against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<Ship<'a>>
+}
+
+impl<'a> Recording<'a> {
+    /// Start recording from a copy of a ship, run with the kinds in `registry`
+    pub fn new(ship: &Ship<'a>, timestep: f64, registry: &BlockRegistry) -> Recording<'a> {
+        Recording {
+            format_version: RECORDING_VERSION,
+            timestep: timestep,
+            kinds: registry.kinds.values().cloned().collect(),
+            ship: ship.clone(),
+            events: vec![],
+            ticks: 0,
+            end: None
+        }
+    }
+
+    /// Add an edit applied after `tick` ticks, which must not be before the last edit
+    pub fn push(&mut self, tick: u64, edit: Edit<'a>) {
+        debug_assert!(self.events.last().is_none_or(|event| event.tick <= tick));
+        self.events.push(Event { tick: tick, edit: edit });
+        self.ticks = self.ticks.max(tick);
+    }
+
+    /// Stop recording after `ticks` ticks, keeping the final state of the ship
+    pub fn finish(&mut self, ticks: u64, ship: &Ship<'a>) {
+        self.ticks = ticks;
+        self.end = Some(ship.clone());
+    }
+
+    /// The block kinds the recording was made with
+    pub fn registry(&self) -> BlockRegistry {
+        let mut registry = BlockRegistry::new();
+        for kind in self.kinds.iter() {
+            registry.insert(kind.clone());
+        }
+        registry
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording<'static>> {
+        let mut file = File::open(path)?;
+
+        let mut string = String::new();
+        file.read_to_string(&mut string)?;
+
+        let recording: Recording<'static> = serde_json::from_str(&string)?;
+        if recording.format_version != RECORDING_VERSION {
+            return Err(Error::Schema(format!("recording format version {} is not supported, expected {}", recording.format_version, RECORDING_VERSION)));
+        }
+        if recording.events.windows(2).any(|pair| pair[0].tick > pair[1].tick) {
+            return Err(Error::Schema("recording events are not in order".to_string()));
+        }
+        Ok(recording)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = File::create(path)?;
+
+        let string = serde_json::to_string(self)?;
+        writeln!(file, "{}", string)?;
+        Ok(())
+    }
+}
+
+/// True if the decks of two ships are identical, down to the bits of every amount
+pub fn same_decks(a: &[Deck], b: &[Deck]) -> bool {
+    // Floats are written with the fewest digits that read back as the same bits, so equal text means equal state
+    match (serde_json::to_string(a), serde_json::to_string(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+/// The state of a replayed ship at some tick
+#[derive(Clone, Debug)]
+struct Keyframe<'a> {
+    tick: u64,
+    next: usize,
+    ship: Ship<'a>
+}
+
+/// Plays a recording forward and backward. The state at tick `t` has run `t` ticks, and applied every edit made before tick `t + 1`.
+#[derive(Clone, Debug)]
+pub struct Replay<'a> {
+    recording: Recording<'a>,
+    /// The kinds of the recording
+    registry: BlockRegistry,
+    pub ship: Ship<'a>,
+    tick: u64,
+    /// Index of the next event to apply
+    next: usize,
+    keyframes: Vec<Keyframe<'a>>,
+    /// Ticks between keyframes, which grows as the replay goes further
+    interval: u64
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(recording: Recording<'a>) -> Result<Replay<'a>> {
+        let mut replay = Replay {
+            registry: recording.registry(),
+            ship: recording.ship.clone(),
+            recording: recording,
+            tick: 0,
+            next: 0,
+            keyframes: vec![],
+            interval: KEYFRAME_TICKS
+        };
+        replay.apply_events()?;
+        replay.keyframe();
+        Ok(replay)
+    }
+
+    pub fn recording(&self) -> &Recording<'a> {
+        &self.recording
+    }
+
+    /// The block kinds the recording was made with, which the replay runs with
+    pub fn registry(&self) -> &BlockRegistry {
+        &self.registry
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Simulated seconds at the current tick
+    pub fn time(&self) -> f64 {
+        self.tick as f64 * self.recording.timestep
+    }
+
+    /// Ticks in the whole recording
+    pub fn ticks(&self) -> u64 {
+        self.recording.ticks
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.tick >= self.recording.ticks
+    }
+
+    /// True if the replay has reached the end of the recording in the same state it was recorded in.
+    /// None before the end, or if the recording did not keep its final state.
+    pub fn matches_end(&self) -> Option<bool> {
+        match self.recording.end {
+            Some(ref end) if self.at_end() => Some(same_decks(&self.ship.decks, &end.decks)),
+            _ => None
+        }
+    }
+
+    fn apply_events(&mut self) -> Result<()> {
+        while let Some(event) = self.recording.events.get(self.next) {
+            if event.tick > self.tick {
+                break;
+            }
+            event.edit.clone().apply(&mut self.ship).map_err(|err| Error::InvalidEdit(format!("event {} at tick {}: {}", self.next, event.tick, err)))?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+
+    fn keyframe(&mut self) {
+        if self.keyframes.last().is_none_or(|keyframe| keyframe.tick < self.tick) {
+            self.keyframes.push(Keyframe {
+                tick: self.tick,
+                next: self.next,
+                ship: self.ship.clone()
+            });
+        }
+
+        if self.keyframes.len() > MAX_KEYFRAMES {
+            let interval = self.interval * 2;
+            self.keyframes.retain(|keyframe| keyframe.tick.is_multiple_of(interval));
+            self.interval = interval;
+        }
+    }
+
+    /// Run one tick and the edits made after it, returning the reports of the tick, or None at the end of the recording
+    pub fn step(&mut self) -> Result<Option<Vec<FlowReport>>> {
+        if self.at_end() {
+            return Ok(None);
+        }
+
+        let reports = self.ship.update(self.recording.timestep, &self.registry);
+        self.tick += 1;
+        self.apply_events()?;
+        if self.tick.is_multiple_of(self.interval) {
+            self.keyframe();
+        }
+        Ok(Some(reports))
+    }
+
+    /// Go to a tick, from the nearest earlier keyframe if it is behind the current tick.
+    /// Ticks past the end go to the end.
+    pub fn seek(&mut self, tick: u64) -> Result<()> {
+        let tick = tick.min(self.recording.ticks);
+        if tick < self.tick {
+            // There is always a keyframe at tick 0
+            let keyframe = self.keyframes.iter().rev().find(|keyframe| keyframe.tick <= tick).unwrap_or(&self.keyframes[0]).clone();
+            self.tick = keyframe.tick;
+            self.next = keyframe.next;
+            self.ship = keyframe.ship;
+        }
+
+        while self.tick < tick {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use deck::Deck;
+    use edit::Edit;
+    use kind::BlockRegistry;
+    use ship::Ship;
+    use simulation::TIMESTEP;
+
+    use super::{same_decks, Recording, Replay, KEYFRAME_TICKS, MAX_KEYFRAMES};
+
+    fn registry() -> BlockRegistry {
+        BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap()
+    }
+
+    /// Run a small ship for `ticks` ticks, recording an edit every 100 ticks, as the engine does
+    fn record(ticks: u64, registry: &BlockRegistry) -> Recording<'static> {
+        let mut ship = Ship::new("Test".to_string());
+        let blocks = vec![
+            Block::from_variant(0, 0, registry.get("Tank").unwrap(), &registry.get("Tank").unwrap().variants[0]),
+            Block::from_kind(1, 0, "Conduit", registry).unwrap(),
+            Block::from_kind(2, 0, "Deck", registry).unwrap(),
+            Block::from_kind(2, 0, "Vent", registry).unwrap(),
+            Block::from_kind(3, 0, "Deck", registry).unwrap()
+        ];
+        ship.decks[0] = Deck::new("Deck 1".to_string(), blocks);
+
+        let mut recording = Recording::new(&ship, TIMESTEP, registry);
+        for tick in 0 .. ticks {
+            if tick % 100 == 50 {
+                // Floors are laid further out, so air keeps spreading and leaking
+                let x = 4 + (tick / 100) as isize;
+                let edit = Edit::AddBlock { deck: 0, index: ship.decks[0].blocks.len(), block: Block::from_kind(x, 0, "Deck", registry).unwrap() };
+                edit.clone().apply(&mut ship).unwrap();
+                recording.push(tick, edit);
+            }
+            ship.update(TIMESTEP, registry);
+        }
+        recording.finish(ticks, &ship);
+        recording
+    }
+
+    #[test]
+    fn replay_matches_end() {
+        let registry = registry();
+        let recording = record(300, &registry);
+        assert_eq!(recording.events.len(), 3);
+
+        let mut replay = Replay::new(recording).unwrap();
+        assert_eq!(replay.matches_end(), None);
+        while replay.step().unwrap().is_some() {}
+        assert_eq!(replay.tick(), 300);
+        assert_eq!(replay.matches_end(), Some(true));
+    }
+
+    #[test]
+    fn replay_uses_recorded_kinds() {
+        let registry = registry();
+        let mut recording = record(300, &registry);
+
+        // The same run with vents that do nothing
+        let mut changed = registry.clone();
+        changed.kinds.get_mut("Vent").unwrap().processes.clear();
+        let expected = record(300, &changed).end.unwrap();
+        assert!(! same_decks(&expected.decks, &recording.end.as_ref().unwrap().decks));
+
+        // A replay follows the kinds stored in the recording, not the ones it was recorded with
+        recording.kinds = changed.kinds.values().cloned().collect();
+        let mut replay = Replay::new(recording).unwrap();
+        replay.seek(300).unwrap();
+        assert!(same_decks(&replay.ship.decks, &expected.decks));
+        assert_eq!(replay.matches_end(), Some(false));
+    }
+
+    #[test]
+    fn seek_backward_matches_stepping_forward() {
+        let registry = registry();
+        let ticks = 2 * KEYFRAME_TICKS + 200;
+        let target = KEYFRAME_TICKS + 100;
+
+        let mut replay = Replay::new(record(ticks, &registry)).unwrap();
+        while replay.tick() < target {
+            replay.step().unwrap();
+        }
+        let stepped = replay.ship.clone();
+
+        // Past the keyframe at 2 * KEYFRAME_TICKS, back to one tick after the target, then to the target
+        replay.seek(ticks).unwrap();
+        replay.seek(target + 1).unwrap();
+        replay.seek(target).unwrap();
+        assert_eq!(replay.tick(), target);
+        assert!(same_decks(&replay.ship.decks, &stepped.decks));
+
+        replay.seek(ticks).unwrap();
+        assert_eq!(replay.matches_end(), Some(true));
+    }
+
+    #[test]
+    fn keyframes_are_thinned_out() {
+        let registry = registry();
+        let mut replay = Replay::new(record(0, &registry)).unwrap();
+
+        // Keyframes as stepping through a long recording would take them
+        for _ in 0 .. 4 * MAX_KEYFRAMES {
+            replay.tick = replay.keyframes.last().unwrap().tick + replay.interval;
+            replay.keyframe();
+            assert!(replay.keyframes.len() <= MAX_KEYFRAMES);
+        }
+        assert!(replay.interval > KEYFRAME_TICKS);
+        assert_eq!(replay.keyframes[0].tick, 0);
+        assert_eq!(replay.keyframes.last().unwrap().tick, replay.tick);
+        assert!(replay.keyframes.windows(2).all(|pair| pair[1].tick - pair[0].tick == replay.interval));
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+
+use deck::Deck;
+use kind::BlockRegistry;
+
+/// A region of a deck where free_air flows freely, bounded by tiles without it, such as hulls and closed doors
+#[derive(Clone, Debug, Default)]
+pub struct Room {
+    /// Positions of every tile in the room
+    pub cells: BTreeSet<(isize, isize)>,
+    /// Total free_air capacity
+    pub volume: f64,
+    /// Total free_air amount
+    pub free_air: f64,
+    /// True if the room touches a tile open to space, and so leaks free_air
+    pub breached: bool
+}
+
+impl Room {
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        self.cells.contains(&(x, y))
+    }
+
+    /// Average free_air pressure, from 0 to 1
+    pub fn pressure(&self) -> f64 {
+        if self.volume > 0.0 {
+            self.free_air/self.volume
+        } else {
+            0.0
+        }
+    }
+}
+
+impl<'a> Deck<'a> {
+    /// True if free_air can flow through a tile, because it has a block with free_air that is not a closed door
+    pub fn holds_air(&self, x: isize, y: isize) -> bool {
+        self.blocks_at(x, y).iter().any(|&i| {
+            let block = &self.blocks[i];
+            block.resources.contains_key("free_air") && ! block.closed()
+        })
+    }
+
+    /// True if a tile stops free_air, because it has a block of an airtight kind that is not an open door.
+    /// Kinds missing from the registry are taken to be airtight.
+    pub fn seals(&self, x: isize, y: isize, registry: &BlockRegistry) -> bool {
+        self.blocks_at(x, y).iter().any(|&i| {
+            let block = &self.blocks[i];
+            block.open != Some(true) && registry.get(&block.kind).is_none_or(|kind| kind.airtight)
+        })
+    }
+
+    /// True if free_air escapes to space through a tile, because it neither holds air nor seals it,
+    /// such as an empty tile or a conduit without a floor
+    pub fn open_to_space(&self, x: isize, y: isize, registry: &BlockRegistry) -> bool {
+        ! self.holds_air(x, y) && ! self.seals(x, y, registry)
+    }
+
+    /// Flood fill the deck into rooms, in the order of their first block
+    pub fn rooms(&self, registry: &BlockRegistry) -> Vec<Room> {
+        let mut rooms = vec![];
+        let mut visited = BTreeSet::new();
+
+        for block in self.blocks.iter() {
+            let start = (block.x, block.y);
+            if visited.contains(&start) || ! self.holds_air(start.0, start.1) {
+                continue;
+            }
+
+            let mut room = Room::default();
+            let mut todo = vec![start];
+            visited.insert(start);
+            while let Some((x, y)) = todo.pop() {
+                room.cells.insert((x, y));
+
+                for &i in self.blocks_at(x, y) {
+                    let block = &self.blocks[i];
+                    if let Some(resource) = block.resources.get("free_air") {
+                        if ! block.closed() {
+                            room.volume += resource.capacity;
+                            room.free_air += resource.amount;
+                        }
+                    }
+                }
+
+                // Any tile nearby that is open to space leaks, matching the vacuum check in Deck::update
+                for dy in -1 .. 2 {
+                    for dx in -1 .. 2 {
+                        if self.open_to_space(x + dx, y + dy, registry) {
+                            room.breached = true;
+                        }
+                    }
+                }
+
+                for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                    let next = (x + dx, y + dy);
+                    if ! visited.contains(&next) && self.holds_air(next.0, next.1) {
+                        visited.insert(next);
+                        todo.push(next);
+                    }
+                }
+            }
+
+            rooms.push(room);
+        }
+
+        rooms
+    }
+}
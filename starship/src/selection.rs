@@ -0,0 +1,104 @@
+use std::cmp::{max, min};
+
+use block::Block;
+use deck::Deck;
+
+/// A rotation or reflection of a group of blocks, within their bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transform {
+    RotateClockwise,
+    RotateCounterClockwise,
+    /// Swap left and right
+    MirrorX,
+    /// Swap top and bottom
+    MirrorY
+}
+
+impl Transform {
+    /// Where the tile (x, y) goes, for a group bounded by `min` and `max`.
+    /// Rotations keep the top left corner of the bounds in place.
+    pub fn apply(&self, x: isize, y: isize, min: (isize, isize), max: (isize, isize)) -> (isize, isize) {
+        match *self {
+            Transform::RotateClockwise => (min.0 + max.1 - y, min.1 + x - min.0),
+            Transform::RotateCounterClockwise => (min.0 + y - min.1, min.1 + max.0 - x),
+            Transform::MirrorX => (min.0 + max.0 - x, y),
+            Transform::MirrorY => (x, min.1 + max.1 - y)
+        }
+    }
+}
+
+/// The smallest and largest positions of some blocks, or None if there are none
+pub fn bounds<'b, 'a: 'b, I: IntoIterator<Item=&'b Block<'a>>>(blocks: I) -> Option<((isize, isize), (isize, isize))> {
+    let mut bounds: Option<((isize, isize), (isize, isize))> = None;
+    for block in blocks {
+        bounds = Some(match bounds {
+            Some(((min_x, min_y), (max_x, max_y))) => ((min(min_x, block.x), min(min_y, block.y)), (max(max_x, block.x), max(max_y, block.y))),
+            None => ((block.x, block.y), (block.x, block.y))
+        });
+    }
+    bounds
+}
+
+/// Every tile in the rectangle with corners `a` and `b`, row by row
+pub fn rect_tiles(a: (isize, isize), b: (isize, isize)) -> Vec<(isize, isize)> {
+    let mut tiles = vec![];
+    for y in min(a.1, b.1) .. max(a.1, b.1) + 1 {
+        for x in min(a.0, b.0) .. max(a.0, b.0) + 1 {
+            tiles.push((x, y));
+        }
+    }
+    tiles
+}
+
+/// Every tile on the line from `a` to `b`, without diagonal gaps that would let free_air through
+pub fn line_tiles(a: (isize, isize), b: (isize, isize)) -> Vec<(isize, isize)> {
+    let (dx, dy) = ((b.0 - a.0).abs(), -(b.1 - a.1).abs());
+    let (sx, sy) = (if a.0 < b.0 { 1 } else { -1 }, if a.1 < b.1 { 1 } else { -1 });
+    let mut err = dx + dy;
+    let (mut x, mut y) = a;
+    let mut tiles = vec![(x, y)];
+    while (x, y) != b {
+        // Step one axis at a time, so consecutive tiles always share an edge
+        if 2 * err - dy > dx - 2 * err {
+            err += dy;
+            x += sx;
+        } else {
+            err += dx;
+            y += sy;
+        }
+        tiles.push((x, y));
+    }
+    tiles
+}
+
+impl<'a> Deck<'a> {
+    /// Indexes of all blocks in the rectangle with corners `a` and `b`
+    pub fn blocks_in(&self, a: (isize, isize), b: (isize, isize)) -> Vec<usize> {
+        let mut indexes = vec![];
+        for (x, y) in rect_tiles(a, b) {
+            indexes.extend_from_slice(self.blocks_at(x, y));
+        }
+        indexes.sort();
+        indexes
+    }
+
+    /// Copies of some blocks, positioned relative to the top left corner of their bounds
+    pub fn copy_blocks(&self, indexes: &[usize]) -> Vec<Block<'a>> {
+        let blocks: Vec<&Block<'a>> = indexes.iter().filter_map(|&i| self.blocks.get(i)).collect();
+        let origin = match bounds(blocks.iter().cloned()) {
+            Some((origin, _)) => origin,
+            None => return vec![]
+        };
+        blocks.into_iter().map(|block| {
+            let mut copy = block.clone();
+            copy.x -= origin.0;
+            copy.y -= origin.1;
+            copy
+        }).collect()
+    }
+
+    /// True if a block of a kind is at (x, y)
+    pub fn has_kind_at(&self, x: isize, y: isize, kind: &str) -> bool {
+        self.blocks_at(x, y).iter().any(|&i| self.blocks[i].kind == kind)
+    }
+}
@@ -1,6 +1,13 @@
-use deck::Deck;
+use std::cmp::{max, min};
+use std::collections::BTreeSet;
+use std::mem;
 
-#[derive(Clone, Debug, RustcDecodable, RustcEncodable)]
+use deck::{Alert, Deck};
+use error::{Error, Result, ValidationError};
+use flow::FlowReport;
+use kind::BlockRegistry;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Ship<'a> {
     pub name: String,
     pub current_deck: usize,
@@ -8,13 +15,224 @@ pub struct Ship<'a> {
 }
 
 impl<'a> Ship<'a> {
-    pub fn update(&mut self) -> bool {
-        let mut redraw = false;
-        for deck in self.decks.iter_mut() {
-            if deck.update() {
-                redraw = true;
+    /// A ship with one empty deck
+    pub fn new(name: String) -> Ship<'a> {
+        Ship {
+            name: name,
+            current_deck: 0,
+            decks: vec![Deck::new("Deck 1".to_string(), vec![])]
+        }
+    }
+
+    /// All triggered sensors, with the index of their deck
+    pub fn alerts(&self) -> Vec<(usize, Alert)> {
+        let mut alerts = vec![];
+        for (d, deck) in self.decks.iter().enumerate() {
+            for alert in deck.alerts() {
+                alerts.push((d, alert));
+            }
+        }
+        alerts
+    }
+
+    fn check_deck(&self, index: usize) -> Result<()> {
+        if index < self.decks.len() {
+            Ok(())
+        } else {
+            Err(Error::InvalidEdit(format!("no deck {}", index)))
+        }
+    }
+
+    /// Insert a deck at `index`, below the deck that was there. `current_deck` stays on the same deck.
+    pub fn insert_deck(&mut self, index: usize, deck: Deck<'a>) -> Result<()> {
+        if index > self.decks.len() {
+            return Err(Error::InvalidEdit(format!("cannot insert deck {}", index)));
+        }
+        self.decks.insert(index, deck);
+        if self.current_deck >= index && self.decks.len() > 1 {
+            self.current_deck += 1;
+        }
+        Ok(())
+    }
+
+    /// Remove a deck, other than the last one. `current_deck` stays on the same deck,
+    /// or moves to the deck below if it was removed.
+    pub fn remove_deck(&mut self, index: usize) -> Result<Deck<'a>> {
+        self.check_deck(index)?;
+        if self.decks.len() == 1 {
+            return Err(Error::InvalidEdit("cannot remove the last deck".to_string()));
+        }
+        let deck = self.decks.remove(index);
+        if self.current_deck > index || (self.current_deck == index && index > 0) {
+            self.current_deck -= 1;
+        }
+        Ok(deck)
+    }
+
+    /// Rename a deck, returning its old name
+    pub fn rename_deck(&mut self, index: usize, name: String) -> Result<String> {
+        self.check_deck(index)?;
+        Ok(mem::replace(&mut self.decks[index].name, name))
+    }
+
+    /// Insert a copy of a deck above it, returning the index of the copy
+    pub fn duplicate_deck(&mut self, index: usize) -> Result<usize> {
+        self.check_deck(index)?;
+        let mut copy = self.decks[index].clone();
+        copy.name = format!("{} copy", copy.name);
+        self.insert_deck(index + 1, copy)?;
+        Ok(index + 1)
+    }
+
+    /// Move a deck from one position in the stack to another. `current_deck` stays on the same deck.
+    pub fn move_deck(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_deck(from)?;
+        self.check_deck(to)?;
+        let deck = self.decks.remove(from);
+        self.decks.insert(to, deck);
+        if self.current_deck == from {
+            self.current_deck = to;
+        } else if from < self.current_deck && self.current_deck <= to {
+            self.current_deck -= 1;
+        } else if to <= self.current_deck && self.current_deck < from {
+            self.current_deck += 1;
+        }
+        Ok(())
+    }
+
+    /// Check for problems that would make the simulation meaningless, reporting all of them at once.
+    ///
+    /// Blocks at the same (x, y) are only duplicates if they are the same kind. Different kinds stack
+    /// on one tile, such as a man or a vent standing on a floor, and the simulation connects them.
+    pub fn validate(&self, registry: &BlockRegistry) -> Result<()> {
+        let mut errors = vec![];
+
+        if self.current_deck >= self.decks.len() {
+            errors.push(ValidationError::CurrentDeckOutOfRange {
+                current_deck: self.current_deck,
+                decks: self.decks.len()
+            });
+        }
+
+        for (d, deck) in self.decks.iter().enumerate() {
+            let mut seen = BTreeSet::new();
+            for block in deck.blocks.iter() {
+                if ! seen.insert((block.x, block.y, block.kind.as_str())) {
+                    errors.push(ValidationError::DuplicateBlock {
+                        deck: d,
+                        x: block.x,
+                        y: block.y,
+                        kind: block.kind.clone()
+                    });
+                }
+
+                if registry.get(&block.kind).is_none() {
+                    errors.push(ValidationError::UnknownKind {
+                        deck: d,
+                        x: block.x,
+                        y: block.y,
+                        kind: block.kind.clone()
+                    });
+                }
+
+                for (name, resource) in block.resources.iter() {
+                    if resource.capacity <= 0.0 {
+                        errors.push(ValidationError::ZeroCapacity {
+                            deck: d,
+                            x: block.x,
+                            y: block.y,
+                            resource: name.to_string()
+                        });
+                    } else if resource.amount > resource.capacity {
+                        errors.push(ValidationError::OverCapacity {
+                            deck: d,
+                            x: block.x,
+                            y: block.y,
+                            resource: name.to_string(),
+                            amount: resource.amount,
+                            capacity: resource.capacity
+                        });
+                    }
+                }
             }
         }
-        redraw
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(errors))
+        }
+    }
+
+    /// The smallest and largest tile positions of any block on any deck, or None if every deck is empty
+    pub fn bounds(&self) -> Option<((isize, isize), (isize, isize))> {
+        let mut bounds: Option<((isize, isize), (isize, isize))> = None;
+        for deck_bounds in self.decks.iter().filter_map(|deck| deck.bounds()) {
+            bounds = Some(match bounds {
+                Some(((min_x, min_y), (max_x, max_y))) => {
+                    let ((x1, y1), (x2, y2)) = deck_bounds;
+                    ((min(min_x, x1), min(min_y, y1)), (max(max_x, x2), max(max_y, y2)))
+                },
+                None => deck_bounds
+            });
+        }
+        bounds
+    }
+
+    /// Update every deck by `dt` seconds, returning the report of each deck in the same order as `decks`.
+    /// Decks are stacked in order, so resources flow between adjacent decks through vertical blocks.
+    pub fn update(&mut self, dt: f64, registry: &BlockRegistry) -> Vec<FlowReport> {
+        Deck::update_all(&mut self.decks, dt, registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use deck::Deck;
+    use error::{Error, ValidationError};
+    use kind::BlockRegistry;
+
+    use super::Ship;
+
+    fn ship_with<'a>(kinds: &[&str], registry: &BlockRegistry) -> Ship<'a> {
+        let mut ship = Ship::new("Test".to_string());
+        let blocks = kinds.iter().map(|kind| Block::from_kind(0, 0, kind, registry).unwrap()).collect();
+        ship.decks[0] = Deck::new("Deck 1".to_string(), blocks);
+        ship
+    }
+
+    #[test]
+    fn remove_deck_moves_current_deck_below() {
+        let mut ship = Ship::new("Test".to_string());
+        ship.decks.push(Deck::new("Deck 2".to_string(), vec![]));
+        ship.decks.push(Deck::new("Deck 3".to_string(), vec![]));
+
+        ship.current_deck = 1;
+        ship.remove_deck(1).unwrap();
+        assert_eq!(ship.current_deck, 0);
+
+        ship.current_deck = 0;
+        ship.remove_deck(0).unwrap();
+        assert_eq!(ship.current_deck, 0);
+        assert_eq!(ship.decks[0].name, "Deck 3");
+    }
+
+    #[test]
+    fn validate_allows_different_kinds_on_one_tile() {
+        let registry = BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap();
+        ship_with(&["Deck", "Vent", "Man"], &registry).validate(&registry).unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_the_same_kind_twice_on_one_tile() {
+        let registry = BlockRegistry::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../res/blocks")).unwrap();
+        match ship_with(&["Deck", "Man", "Deck"], &registry).validate(&registry) {
+            Err(Error::Validation(errors)) => match errors.as_slice() {
+                [ValidationError::DuplicateBlock { deck: 0, x: 0, y: 0, ref kind }] => assert_eq!(kind, "Deck"),
+                _ => panic!("expected one duplicate block, got {:?}", errors)
+            },
+            other => panic!("expected a validation error, got {:?}", other)
+        }
     }
 }
@@ -0,0 +1,125 @@
+use flow::FlowReport;
+use kind::BlockRegistry;
+use ship::Ship;
+
+/// Simulated seconds per tick, unless a tool is given another
+pub const TIMESTEP: f64 = 0.01;
+
+/// Speed multipliers selectable with `faster` and `slower`
+pub const SPEEDS: [f64; 7] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+/// Most ticks run by one call to `advance`, so a slow machine falls behind instead of stalling
+pub const MAX_TICKS: usize = 256;
+
+/// Drives a ship with a fixed timestep, independent of how often `advance` is called
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    /// Simulated seconds per tick
+    pub timestep: f64,
+    /// Number of ticks run so far
+    pub ticks: u64,
+    pub paused: bool,
+    /// Index into `SPEEDS`
+    speed: usize,
+    /// Simulated seconds not yet run
+    accumulator: f64,
+    /// Ticks requested by `step` while paused
+    steps: usize
+}
+
+impl Simulation {
+    pub fn new(timestep: f64) -> Simulation {
+        Simulation {
+            timestep: timestep,
+            ticks: 0,
+            paused: false,
+            speed: 2,
+            accumulator: 0.0,
+            steps: 0
+        }
+    }
+
+    /// Simulated seconds run so far
+    pub fn time(&self) -> f64 {
+        self.ticks as f64 * self.timestep
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    pub fn faster(&mut self) {
+        if self.speed + 1 < SPEEDS.len() {
+            self.speed += 1;
+        }
+    }
+
+    pub fn slower(&mut self) {
+        if self.speed > 0 {
+            self.speed -= 1;
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.accumulator = 0.0;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    /// Run a single tick on the next `advance`, pausing if running
+    pub fn step(&mut self) {
+        self.pause();
+        self.steps += 1;
+    }
+
+    /// Advance by `elapsed` real seconds, running as many whole ticks as fit at the current speed.
+    /// Returns the reports of each tick that was run.
+    pub fn advance(&mut self, ship: &mut Ship, registry: &BlockRegistry, elapsed: f64) -> Vec<Vec<FlowReport>> {
+        self.advance_with(ship, registry, elapsed, |_, _, _| ())
+    }
+
+    /// Like `advance`, calling `after_tick` with the ship, the tick count and the simulated time after each tick,
+    /// such as to record telemetry
+    pub fn advance_with<F: FnMut(&Ship, u64, f64)>(&mut self, ship: &mut Ship, registry: &BlockRegistry, elapsed: f64, mut after_tick: F) -> Vec<Vec<FlowReport>> {
+        let ticks = self.due(elapsed);
+        let mut reports = Vec::with_capacity(ticks);
+        for _ in 0 .. ticks {
+            reports.push(ship.update(self.timestep, registry));
+            self.ticks += 1;
+            after_tick(ship, self.ticks, self.time());
+        }
+        reports
+    }
+
+    /// Take the number of ticks due after `elapsed` real seconds, without running them,
+    /// such as to play back a recording at the current speed
+    pub fn due(&mut self, elapsed: f64) -> usize {
+        let mut ticks = 0;
+        if self.paused {
+            ticks = self.steps;
+            self.steps = 0;
+        } else {
+            self.accumulator += elapsed * self.speed();
+            while self.accumulator >= self.timestep && ticks < MAX_TICKS {
+                self.accumulator -= self.timestep;
+                ticks += 1;
+            }
+
+            if ticks == MAX_TICKS {
+                self.accumulator = 0.0;
+            }
+        }
+        ticks
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use error::Result;
+use ship::Ship;
+
+/// A set of tiles on one deck whose totals are recorded alongside the deck totals, such as a room or a selection
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub name: String,
+    /// Index of the deck in `Ship::decks`
+    pub deck: usize,
+    pub tiles: BTreeSet<(isize, isize)>
+}
+
+impl Watch {
+    pub fn new(name: String, deck: usize, tiles: BTreeSet<(isize, isize)>) -> Watch {
+        Watch {
+            name: name,
+            deck: deck,
+            tiles: tiles
+        }
+    }
+
+    /// Total amount of each resource in the blocks on the watched tiles
+    pub fn totals(&self, ship: &Ship) -> BTreeMap<String, f64> {
+        let mut totals = BTreeMap::new();
+        if let Some(deck) = ship.decks.get(self.deck) {
+            for &(x, y) in self.tiles.iter() {
+                for &i in deck.blocks_at(x, y) {
+                    for (name, resource) in deck.blocks[i].resources.iter() {
+                        *totals.entry(name.to_string()).or_insert(0.0) += resource.amount;
+                    }
+                }
+            }
+        }
+        totals
+    }
+}
+
+/// What a series of totals is taken from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Source {
+    /// The whole of a deck, by index in `Ship::decks`
+    Deck(usize),
+    /// A watch, by index in `Telemetry::watches`
+    Watch(usize)
+}
+
+/// Resource totals at the end of one tick
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub tick: u64,
+    /// Simulated seconds since the simulation started
+    pub time: f64,
+    /// Totals of each deck, in deck order
+    pub decks: Vec<BTreeMap<String, f64>>,
+    /// Totals of each watch, in the order of `Telemetry::watches`
+    pub watches: Vec<BTreeMap<String, f64>>
+}
+
+impl Sample {
+    pub fn new(ship: &Ship, watches: &[Watch], tick: u64, time: f64) -> Sample {
+        Sample {
+            tick: tick,
+            time: time,
+            decks: ship.decks.iter().map(|deck| deck.totals()).collect(),
+            watches: watches.iter().map(|watch| watch.totals(ship)).collect()
+        }
+    }
+
+    pub fn totals(&self, source: Source) -> Option<&BTreeMap<String, f64>> {
+        match source {
+            Source::Deck(i) => self.decks.get(i),
+            Source::Watch(i) => self.watches.get(i)
+        }
+    }
+}
+
+/// A recorder of resource totals over time, keeping the most recent samples in a ring buffer
+#[derive(Clone, Debug)]
+pub struct Telemetry {
+    /// Most samples kept, after which the oldest are dropped
+    pub capacity: usize,
+    /// Ticks between samples, so that a long history fits in the buffer
+    pub interval: u64,
+    pub watches: Vec<Watch>,
+    samples: VecDeque<Sample>
+}
+
+impl Telemetry {
+    pub fn new(capacity: usize, interval: u64) -> Telemetry {
+        Telemetry {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            watches: Vec::new(),
+            samples: VecDeque::new()
+        }
+    }
+
+    /// Record the state of a ship after a tick, if the tick falls on the interval
+    pub fn record(&mut self, ship: &Ship, tick: u64, time: f64) {
+        if ! tick.is_multiple_of(self.interval) {
+            return;
+        }
+
+        let sample = Sample::new(ship, &self.watches, tick, time);
+        self.push(sample);
+    }
+
+    /// Keep a sample taken elsewhere, such as on another thread, dropping the oldest if full.
+    /// A sample with totals for more watches than there are was taken before a watch was removed, and is dropped.
+    pub fn push(&mut self, sample: Sample) {
+        if sample.watches.len() > self.watches.len() {
+            return;
+        }
+
+        while self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Samples from oldest to newest
+    pub fn samples(&self) -> &VecDeque<Sample> {
+        &self.samples
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Forget every sample, keeping the watches
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Start recording the totals of some tiles. Earlier samples have no totals for the new watch.
+    pub fn watch(&mut self, watch: Watch) -> usize {
+        self.watches.push(watch);
+        self.watches.len() - 1
+    }
+
+    /// Stop recording a watch, and drop its totals from every sample
+    pub fn unwatch(&mut self, index: usize) -> Option<Watch> {
+        if index >= self.watches.len() {
+            return None;
+        }
+
+        for sample in self.samples.iter_mut() {
+            if index < sample.watches.len() {
+                sample.watches.remove(index);
+            }
+        }
+        Some(self.watches.remove(index))
+    }
+
+    /// Every resource with a total from a source in any sample
+    pub fn resources(&self, source: Source) -> BTreeSet<String> {
+        let mut resources = BTreeSet::new();
+        for totals in self.samples.iter().filter_map(|sample| sample.totals(source)) {
+            resources.extend(totals.keys().cloned());
+        }
+        resources
+    }
+
+    /// (time, total) of one resource from one source, oldest first, skipping samples without that source
+    pub fn series(&self, source: Source, resource: &str) -> Vec<(f64, f64)> {
+        self.samples.iter().filter_map(|sample| {
+            sample.totals(source).map(|totals| (sample.time, totals.get(resource).cloned().unwrap_or(0.0)))
+        }).collect()
+    }
+
+    /// Write every sample as CSV, one row per sample, source and resource.
+    /// Deck rows have an empty watch column, and watch rows give the deck of the watch.
+    pub fn write_csv<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        writeln!(output, "tick,time,deck,watch,resource,total")?;
+        for sample in self.samples.iter() {
+            for (deck, totals) in sample.decks.iter().enumerate() {
+                for (resource, total) in totals.iter() {
+                    writeln!(output, "{},{},{},,{},{}", sample.tick, sample.time, deck, resource, total)?;
+                }
+            }
+            for (watch, totals) in self.watches.iter().zip(sample.watches.iter()) {
+                for (resource, total) in totals.iter() {
+                    writeln!(output, "{},{},{},\"{}\",{},{}", sample.tick, sample.time, watch.deck, watch.name.replace('"', "\"\""), resource, total)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+}